@@ -0,0 +1,291 @@
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+
+use crate::detectors::DescriptorExtractor;
+use crate::image::GrayFloatImage;
+use crate::KeyPoint;
+
+/// 512-bit BRISK descriptor.
+pub type BriskDescriptor = [u8; 64];
+
+/// Radii and point counts of the concentric rings of the sampling pattern, for a
+/// keypoint of size [`BRISK_BASE_SIZE`].
+const RADII: [f32; 5] = [0.0, 2.9, 4.9, 7.4, 10.8];
+const POINTS: [usize; 5] = [1, 10, 14, 15, 20];
+const RADIUS_SCALE: f32 = 0.85;
+const SIGMA_SCALE: f32 = 1.3;
+
+/// Pairs closer than this are compared for the descriptor bits...
+const D_MAX: f32 = 5.85;
+/// ...and pairs further apart than this estimate the orientation.
+const D_MIN: f32 = 8.2;
+
+/// Keypoint size the pattern is defined for; it is scaled by `size / BRISK_BASE_SIZE`.
+pub const BRISK_BASE_SIZE: f32 = 12.0;
+
+/// A sampling point and the standard deviation of its smoothing.
+#[derive(Copy, Clone, Debug)]
+struct PatternPoint {
+    x: f32,
+    y: f32,
+    sigma: f32,
+}
+
+struct Pattern {
+    points: Vec<PatternPoint>,
+    short_pairs: Vec<(usize, usize)>,
+    long_pairs: Vec<(usize, usize)>,
+}
+
+/// BRISK sampling pattern (Leutenegger et al., "BRISK: Binary Robust Invariant
+/// Scalable Keypoints"): 60 points on five rings, 512 short pairs and 870 long
+/// pairs.
+fn pattern() -> &'static Pattern {
+    static PATTERN: OnceLock<Pattern> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        let mut points = vec![];
+        for (radius, count) in RADII.iter().zip(POINTS) {
+            let radius = radius * RADIUS_SCALE;
+            let sigma = if count == 1 {
+                SIGMA_SCALE * 0.5
+            } else {
+                SIGMA_SCALE * radius * (PI / count as f32).sin()
+            };
+            for k in 0..count {
+                let theta = 2.0 * PI * k as f32 / count as f32;
+                points.push(PatternPoint { x: radius * theta.cos(), y: radius * theta.sin(), sigma });
+            }
+        }
+
+        let (mut short_pairs, mut long_pairs) = (vec![], vec![]);
+        for i in 1..points.len() {
+            for j in 0..i {
+                let d2 = (points[i].x - points[j].x).powi(2) + (points[i].y - points[j].y).powi(2);
+                if d2 < D_MAX * D_MAX {
+                    short_pairs.push((i, j));
+                } else if d2 > D_MIN * D_MIN {
+                    long_pairs.push((i, j));
+                }
+            }
+        }
+        short_pairs.truncate(512);
+
+        Pattern { points, short_pairs, long_pairs }
+    })
+}
+
+/// Integral image in `f64` with a row and column of zeros in front, so that
+/// `sum[y][x]` holds the sum of the pixels above and left of `(x, y)`.
+pub struct IntegralImage {
+    width: usize,
+    height: usize,
+    sum: Vec<f64>,
+}
+
+impl IntegralImage {
+    pub fn new(image: &GrayFloatImage) -> IntegralImage {
+        let (width, height) = (image.width(), image.height());
+        let stride = width + 1;
+        let mut sum = vec![0f64; stride * (height + 1)];
+        for y in 0..height {
+            let mut row = 0f64;
+            for x in 0..width {
+                row += image.get(x, y) as f64;
+                sum[(y + 1) * stride + x + 1] = sum[y * stride + x + 1] + row;
+            }
+        }
+        IntegralImage { width, height, sum }
+    }
+
+    /// Sum of the image over `[0, u) x [0, v)` in pixel-edge coordinates, exact for
+    /// fractional `u` and `v` since pixels are constant over their area.
+    fn area(&self, u: f32, v: f32) -> f64 {
+        let (u, v) = (u.clamp(0.0, self.width as f32) as f64, v.clamp(0.0, self.height as f32) as f64);
+        let (x0, y0) = ((u.floor() as usize).min(self.width - 1), (v.floor() as usize).min(self.height - 1));
+        let (fx, fy) = (u - x0 as f64, v - y0 as f64);
+        let stride = self.width + 1;
+        let at = |x: usize, y: usize| self.sum[y * stride + x];
+
+        let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1, y0) * fx;
+        let bottom = at(x0, y0 + 1) * (1.0 - fx) + at(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    /// Mean of the image over the square of half side `half` centered at the
+    /// sub-pixel position `(x, y)`.
+    pub fn box_mean(&self, x: f32, y: f32, half: f32) -> f32 {
+        // Pixel centers are at integer coordinates, edges at half integers.
+        let (x0, y0, x1, y1) = (x + 0.5 - half, y + 0.5 - half, x + 0.5 + half, y + 0.5 + half);
+        let sum = self.area(x1, y1) - self.area(x0, y1) - self.area(x1, y0) + self.area(x0, y0);
+        (sum / (4.0 * half as f64 * half as f64)) as f32
+    }
+}
+
+/// Smoothed intensity at a pattern point: bilinear interpolation for small
+/// kernels and a box filter of half side `sigma` otherwise, as BRISK does.
+fn smoothed_value(image: &GrayFloatImage, integral: &IntegralImage, x: f32, y: f32, sigma: f32) -> f32 {
+    if sigma < 0.5 {
+        image.sample_bilinear(x, y)
+    } else {
+        integral.box_mean(x, y, sigma)
+    }
+}
+
+/// Orientation of the keypoint in degrees in `[0, 360)`, from the mean local
+/// gradient along the long pairs of the pattern.
+pub fn brisk_orientation(image: &GrayFloatImage, integral: &IntegralImage, keypoint: &KeyPoint) -> f32 {
+    let pattern = pattern();
+    let scale = keypoint.size / BRISK_BASE_SIZE;
+    let values: Vec<f32> = pattern
+        .points
+        .iter()
+        .map(|p| smoothed_value(image, integral, keypoint.x + p.x * scale, keypoint.y + p.y * scale, p.sigma * scale))
+        .collect();
+
+    let (mut gx, mut gy) = (0f32, 0f32);
+    for &(i, j) in pattern.long_pairs.iter() {
+        let (pi, pj) = (pattern.points[i], pattern.points[j]);
+        let (dx, dy) = (pj.x - pi.x, pj.y - pi.y);
+        let weight = (values[j] - values[i]) / (dx * dx + dy * dy);
+        gx += weight * dx;
+        gy += weight * dy;
+    }
+
+    let angle = gy.atan2(gx).to_degrees();
+    if angle < 0.0 {
+        angle + 360.0
+    } else {
+        angle
+    }
+}
+
+/// BRISK descriptor of a keypoint, with the pattern scaled to the keypoint size
+/// and rotated by `angle` degrees. Bit `i` is set when the second point of short
+/// pair `i` is brighter than the first one.
+pub fn compute_brisk_descriptor(
+    image: &GrayFloatImage,
+    integral: &IntegralImage,
+    keypoint: &KeyPoint,
+    angle: f32,
+) -> BriskDescriptor {
+    let pattern = pattern();
+    let scale = keypoint.size / BRISK_BASE_SIZE;
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let values: Vec<f32> = pattern
+        .points
+        .iter()
+        .map(|p| {
+            let (u, v) = ((p.x * cos - p.y * sin) * scale, (p.x * sin + p.y * cos) * scale);
+            smoothed_value(image, integral, keypoint.x + u, keypoint.y + v, p.sigma * scale)
+        })
+        .collect();
+
+    let mut descriptor = [0u8; 64];
+    for (bit, &(i, j)) in pattern.short_pairs.iter().enumerate() {
+        if values[j] > values[i] {
+            descriptor[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    descriptor
+}
+
+/// BRISK descriptors behind the common extractor interface. Keypoint sizes set the
+/// pattern scale, so FAST or ORB keypoints (size 31) are sampled over a radius of
+/// about 24 pixels.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BriskExtractor {
+    /// Use the keypoint `angle` instead of estimating BRISK's own orientation.
+    pub use_keypoint_orientation: bool,
+}
+
+impl DescriptorExtractor for BriskExtractor {
+    type Descriptor = BriskDescriptor;
+
+    fn compute(&self, image: &GrayFloatImage, keypoints: &[KeyPoint]) -> Vec<BriskDescriptor> {
+        let integral = IntegralImage::new(image);
+        keypoints
+            .iter()
+            .map(|keypoint| {
+                let angle = if self.use_keypoint_orientation {
+                    keypoint.angle
+                } else {
+                    brisk_orientation(image, &integral, keypoint)
+                };
+                compute_brisk_descriptor(image, &integral, keypoint, angle)
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{pattern, BriskExtractor, IntegralImage};
+    use crate::detectors::{DescriptorExtractor, FastDetector};
+    use crate::evaluation::{evaluate, rotation_about, warp_perspective, EvaluationParams};
+    use crate::hamming::hamming_distance;
+    use crate::image::{gaussian_blur, noise_image, GrayFloatImage};
+    use crate::KeyPoint;
+
+    fn texture(size: u32) -> GrayFloatImage {
+        gaussian_blur(&noise_image(size, 12345), 1.5)
+    }
+
+    #[test]
+    fn pattern_has_brisk_pair_counts() {
+        let pattern = pattern();
+        assert_eq!(pattern.points.len(), 60);
+        assert_eq!(pattern.short_pairs.len(), 512);
+        assert_eq!(pattern.long_pairs.len(), 870);
+    }
+
+    #[test]
+    fn box_mean_matches_direct_average() {
+        let image = texture(20);
+        let integral = IntegralImage::new(&image);
+
+        let direct: f32 = (4..=6).flat_map(|y| (7..=9).map(move |x| (x, y))).map(|(x, y)| image.get(x, y)).sum::<f32>() / 9.0;
+        assert!((integral.box_mean(8.0, 5.0, 1.5) - direct).abs() < 1e-5);
+
+        // Half a pixel to the right averages the two neighbours.
+        let half = (image.get(3, 3) + image.get(4, 3)) / 2.0;
+        assert!((integral.box_mean(3.5, 3.0, 0.5) - half).abs() < 1e-5);
+    }
+
+    #[test]
+    fn brisk_is_rotation_invariant() {
+        let size = 129;
+        let image = texture(size);
+        let center = size as f32 / 2.0 - 0.5;
+        let h = rotation_about(60.0, center, center);
+        let rotated = warp_perspective(&image, &h, size, size);
+
+        let extractor = BriskExtractor::default();
+        let keypoint = KeyPoint::new(center, center, 31.0);
+        let a = extractor.compute(&image, &[keypoint])[0];
+        let b = extractor.compute(&rotated, &[keypoint])[0];
+        let shifted = extractor.compute(&image, &[KeyPoint::new(center + 20.0, center - 15.0, 31.0)])[0];
+
+        let distance = hamming_distance(&a, &b);
+        assert!(distance < 80, "hamming distance {}", distance);
+        assert!(hamming_distance(&a, &shifted) > 150);
+    }
+
+    #[test]
+    fn brisk_with_fast_keypoints_matches_warps() {
+        let image = GrayFloatImage::load_image("input-image/test1.png");
+        let (width, height) = (image.width() as u32, image.height() as u32);
+        let h = rotation_about(20.0, width as f32 / 2.0, height as f32 / 2.0);
+        let warped = warp_perspective(&image, &h, width, height);
+
+        let evaluation = evaluate(
+            &FastDetector::default(),
+            &BriskExtractor::default(),
+            &image,
+            &warped,
+            &h,
+            &EvaluationParams::default(),
+        );
+        assert!(evaluation.correct_matches > 0, "{:?}", evaluation);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::image::GrayFloatImage;
+use std::sync::OnceLock;
+
+use crate::image::{gaussian_blur_with_size, GrayFloatImage};
+use crate::KeyPoint;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Corner {
@@ -13,146 +16,259 @@ impl Corner {
     }
 }
 
+/// Diameter of the circular patch used for ORB orientation and descriptors.
+pub const PATCH_SIZE: usize = 31;
+pub const HALF_PATCH_SIZE: usize = 15;
+
+/// Variants of the FAST corner detector. A point is a corner if a contiguous arc of
+/// at least the given length on the surrounding circle is entirely brighter or
+/// entirely darker than the center by more than the threshold. `Twelve` and `Nine`
+/// use the 16-pixel Bresenham circle of radius 3, `Seven` the 12-pixel circle of
+/// radius 2 and `Five` the 8-pixel circle of radius 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Fast {
+    /// Corners require a section of length as least twelve.
+    Twelve,
     /// Corners require a section of length as least nine.
     Nine,
+    /// Corners require a section of length as least seven.
+    Seven,
+    /// Corners require a section of length as least five.
+    Five,
 }
 
+// Note [FAST circle labels]
+//
+//          15 00 01
+//       14          02
+//     13              03
+//     12       p      04
+//     11              05
+//       10          06
+//          09 08 07
+const CIRCLE_16: [(i32, i32); 16] = [
+    (0, -3), (1, -3), (2, -2), (3, -1),
+    (3, 0), (3, 1), (2, 2), (1, 3),
+    (0, 3), (-1, 3), (-2, 2), (-3, 1),
+    (-3, 0), (-3, -1), (-2, -2), (-1, -3),
+];
 
-unsafe fn get_circle(
-    image: &GrayFloatImage,
-    x: u32,
-    y: u32,
-    p0: i16,
-    p4: i16,
-    p8: i16,
-    p12: i16,
-) -> [i16; 16] {
-    [
-        p0,
-        image.unsafe_get_pixel(x + 1, y - 3)[0] as i16,
-        image.unsafe_get_pixel(x + 2, y - 2)[0] as i16,
-        image.unsafe_get_pixel(x + 3, y - 1)[0] as i16,
-        p4,
-        image.unsafe_get_pixel(x + 3, y + 1)[0] as i16,
-        image.unsafe_get_pixel(x + 2, y + 2)[0] as i16,
-        image.unsafe_get_pixel(x + 1, y + 3)[0] as i16,
-        p8,
-        image.unsafe_get_pixel(x - 1, y + 3)[0] as i16,
-        image.unsafe_get_pixel(x - 2, y + 2)[0] as i16,
-        image.unsafe_get_pixel(x - 3, y + 1)[0] as i16,
-        p12,
-        image.unsafe_get_pixel(x - 3, y - 1)[0] as i16,
-        image.unsafe_get_pixel(x - 2, y - 2)[0] as i16,
-        image.unsafe_get_pixel(x - 1, y - 3)[0] as i16,
-    ]
-}
-
-fn is_corner_fast9(image: &GrayFloatImage, threshold: u8, x: u32, y: u32) -> bool {
-    let (width, height) = image.dimensions();
-    if x >= u32::MAX -3 
-    || y >= u32::MAX -3 
-    || x < 3
-    || y < 3
-    || width <= x + 3
-    || height <= y + 3
-    {
-        return false
-    }
+const CIRCLE_12: [(i32, i32); 12] = [
+    (0, -2), (1, -2), (2, -1),
+    (2, 0), (2, 1), (1, 2),
+    (0, 2), (-1, 2), (-2, 1),
+    (-2, 0), (-2, -1), (-1, -2),
+];
 
-    let c = unsafe { image.unsafe_get_pixel(x, y)[0] };
-    let low_thresh: i16 = c as i16 - threshold as i16;
-    let high_thresh: i16 = c as i16 + threshold as i16;
+const CIRCLE_8: [(i32, i32); 8] = [
+    (0, -1), (1, -1),
+    (1, 0), (1, 1),
+    (0, 1), (-1, 1),
+    (-1, 0), (-1, -1),
+];
 
-    let (p0, p4, p8, p12) = unsafe {
-        (
-            image.unsafe_get_pixel(x, y - 3)[0] as i16,
-            image.unsafe_get_pixel(x, y + 3)[0] as i16,
-            image.unsafe_get_pixel(x + 3, y)[0] as i16,
-            image.unsafe_get_pixel(x - 3, y)[0] as i16,
-        )
-    };
+static ARC_TABLE_TWELVE: OnceLock<ArcTable> = OnceLock::new();
+static ARC_TABLE_NINE: OnceLock<ArcTable> = OnceLock::new();
+static ARC_TABLE_SEVEN: OnceLock<ArcTable> = OnceLock::new();
+static ARC_TABLE_FIVE: OnceLock<ArcTable> = OnceLock::new();
 
-    
-
-    let above = (p0 > high_thresh && p4 > high_thresh)
-        || (p4 > high_thresh && p8 > high_thresh)
-        || (p8 > high_thresh && p12 > high_thresh)
-        || (p12 > high_thresh && p0 > high_thresh);
+impl Fast {
+    pub fn arc_length(&self) -> usize {
+        match self {
+            Fast::Twelve => 12,
+            Fast::Nine => 9,
+            Fast::Seven => 7,
+            Fast::Five => 5,
+        }
+    }
 
-    let below = (p0 < low_thresh && p4 < low_thresh)
-        || (p4 < low_thresh && p8 < low_thresh)
-        || (p8 < low_thresh && p12 < low_thresh)
-        || (p12 < low_thresh && p0 < low_thresh);
+    /// Circle offsets, clockwise from the pixel straight above the center.
+    pub fn circle(&self) -> &'static [(i32, i32)] {
+        match self {
+            Fast::Twelve | Fast::Nine => &CIRCLE_16,
+            Fast::Seven => &CIRCLE_12,
+            Fast::Five => &CIRCLE_8,
+        }
+    }
 
-    if !above && !below {
-        return false;
+    pub fn radius(&self) -> u32 {
+        match self {
+            Fast::Twelve | Fast::Nine => 3,
+            Fast::Seven => 2,
+            Fast::Five => 1,
+        }
     }
-    
-        // JUSTIFICATION - see comment at the start of this function
-    let pixels = unsafe { get_circle(image, x, y, p0, p4, p8, p12) };
 
-    (above && has_bright_span(&pixels, 9, high_thresh))
-        || (below && has_dark_span(&pixels, 9, low_thresh))
+    fn arc_table(&self) -> &'static ArcTable {
+        let cell = match self {
+            Fast::Twelve => &ARC_TABLE_TWELVE,
+            Fast::Nine => &ARC_TABLE_NINE,
+            Fast::Seven => &ARC_TABLE_SEVEN,
+            Fast::Five => &ARC_TABLE_FIVE,
+        };
+        cell.get_or_init(|| ArcTable::new(self.circle().len(), self.arc_length()))
+    }
+}
 
+/// Precomputed answer to "does this circle mask contain a contiguous arc of the
+/// required length?" for every possible mask, stored as a bitset. Bit `i` of a mask
+/// is set when circle pixel `i` passes the brightness (or darkness) test, so the
+/// segment test becomes a single table lookup instead of a scan around the circle.
+struct ArcTable {
+    bits: Vec<u64>,
 }
 
+impl ArcTable {
+    fn new(circle_len: usize, arc_length: usize) -> Self {
+        let size = 1usize << circle_len;
+        let mut bits = vec![0u64; size.div_ceil(64)];
+        for mask in 0..size {
+            if has_circular_arc(mask as u32, circle_len, arc_length) {
+                bits[mask / 64] |= 1 << (mask % 64);
+            }
+        }
+        ArcTable { bits }
+    }
 
-fn has_bright_span(circle: &[i16; 16], length: u8, threshold: i16) -> bool {
-    search_span(circle, length, |c| *c > threshold)
+    #[inline]
+    fn contains(&self, mask: u32) -> bool {
+        let mask = mask as usize;
+        self.bits[mask / 64] & (1 << (mask % 64)) != 0
+    }
 }
 
-/// True if the circle has a contiguous section of at least the given length, all
-/// of whose pixels have intensities strictly less than the threshold.
-fn has_dark_span(circle: &[i16; 16], length: u8, threshold: i16) -> bool {
-    search_span(circle, length, |c| *c < threshold)
+/// True if `mask`, read as a circle of `circle_len` bits, has a run of at least
+/// `arc_length` set bits, wrapping around from the last bit to the first.
+fn has_circular_arc(mask: u32, circle_len: usize, arc_length: usize) -> bool {
+    let unrolled = (mask as u64) | ((mask as u64) << circle_len);
+    let mut run = unrolled;
+    for _ in 1..arc_length {
+        run &= run >> 1;
+    }
+    run & ((1u64 << circle_len) - 1) != 0
 }
 
-fn search_span<F>(circle: &[i16; 16], length: u8, f: F) -> bool
-where
-    F: Fn(&i16) -> bool,
-{
-    if length > 16 {
+/// `GrayFloatImage` stores intensities normalised to `0..=1`, while FAST thresholds
+/// are given in 8-bit intensity units. Pixels are rescaled to `0..=255` before the
+/// segment test so that thresholds behave the same as on the original 8-bit image.
+///
+/// # Safety
+///
+/// The caller must ensure that `(x, y)` lies inside the image.
+#[inline]
+unsafe fn intensity(image: &GrayFloatImage, x: u32, y: u32) -> i16 {
+    (image.unsafe_get_pixel(x, y)[0] * 255.0).round() as i16
+}
+
+/// Checks if the given pixel is a corner according to the given FAST variant.
+fn is_corner(image: &GrayFloatImage, threshold: u8, x: u32, y: u32, variant: Fast) -> bool {
+    // UNSAFETY JUSTIFICATION
+    //  All pixel accesses below are at offsets from the variant's circle, whose
+    //  coordinates lie in [-radius, radius]. The precondition below guarantees
+    //  that these are within image bounds.
+    let (width, height) = image.dimensions();
+    let radius = variant.radius();
+    if x < radius
+        || y < radius
+        || x >= u32::MAX - radius
+        || y >= u32::MAX - radius
+        || width <= x + radius
+        || height <= y + radius
+    {
         return false;
     }
 
-    let mut nb_ok = 0u8;
-    let mut nb_ok_start = None;
+    let pixel = |(dx, dy): (i32, i32)| unsafe {
+        intensity(image, (x as i32 + dx) as u32, (y as i32 + dy) as u32)
+    };
+
+    let c = pixel((0, 0));
+    let low_thresh: i16 = c - threshold as i16;
+    let high_thresh: i16 = c + threshold as i16;
 
-    for c in circle.iter() {
-        if f(c) {
-            nb_ok += 1;
-            if nb_ok == length {
-                return true;
-            }
-        } else {
-            if nb_ok_start.is_none() {
-                nb_ok_start = Some(nb_ok);
-            }
-            nb_ok = 0;
+    let circle = variant.circle();
+    let arc_length = variant.arc_length();
+
+    // Any arc of the required length covers at least this many of the four
+    // compass pixels, so most candidates are rejected after four reads.
+    let quarter = circle.len() / 4;
+    let min_compass = arc_length / quarter;
+    let (mut bright, mut dark) = (0, 0);
+    for offset in circle.iter().step_by(quarter) {
+        let p = pixel(*offset);
+        if p > high_thresh {
+            bright += 1;
+        } else if p < low_thresh {
+            dark += 1;
         }
     }
 
-    nb_ok + nb_ok_start.unwrap() >= length
+    if bright < min_compass && dark < min_compass {
+        return false;
+    }
+
+    let (mut bright_mask, mut dark_mask) = (0u32, 0u32);
+    for (i, offset) in circle.iter().enumerate() {
+        let p = pixel(*offset);
+        if p > high_thresh {
+            bright_mask |= 1 << i;
+        } else if p < low_thresh {
+            dark_mask |= 1 << i;
+        }
+    }
+
+    let table = variant.arc_table();
+    table.contains(bright_mask) || table.contains(dark_mask)
 }
 
-pub fn float_corners_fast9(image: &GrayFloatImage, threshold: u8) -> Vec<Corner> {
+/// Finds corners using the given FAST variant. `threshold` is expressed in 8-bit
+/// intensity units, see [`intensity`].
+pub fn float_corners_fast(image: &GrayFloatImage, threshold: u8, variant: Fast) -> Vec<Corner> {
     let (width, height) = (image.width(), image.height());
     let mut corners = vec![];
 
     for y in 0..height {
         for x in 0..width {
-            if is_corner_fast9(image, threshold, x as u32, y as u32) {
-                let score = fast_corner_score(image, threshold, x as u32, y as u32, Fast::Nine);
+            if is_corner(image, threshold, x as u32, y as u32, variant) {
+                let score = fast_corner_score(image, threshold, x as u32, y as u32, variant);
                 corners.push(Corner::new(x as u32, y as u32, score as f32));
             }
         }
     }
 
     corners
+}
+
+/// Keeps the corners whose score is strictly greater than the score of every
+/// corner in their 3x3 neighbourhood.
+pub fn fast_non_max_suppression(corners: &[Corner], width: u32, height: u32) -> Vec<Corner> {
+    let (width, height) = (width as usize, height as usize);
+    let mut scores = vec![0f32; width * height];
+    for corner in corners {
+        scores[corner.y as usize * width + corner.x as usize] = corner.score;
+    }
+
+    corners
+        .iter()
+        .filter(|corner| {
+            let (x, y) = (corner.x as usize, corner.y as usize);
+            (y.saturating_sub(1)..(y + 2).min(height)).all(|ny| {
+                (x.saturating_sub(1)..(x + 2).min(width))
+                    .all(|nx| (nx, ny) == (x, y) || scores[ny * width + nx] < corner.score)
+            })
+        })
+        .copied()
+        .collect()
+}
 
+/// Finds corners using FAST-9 features. `threshold` is expressed in 8-bit
+/// intensity units, see [`intensity`].
+pub fn float_corners_fast9(image: &GrayFloatImage, threshold: u8) -> Vec<Corner> {
+    float_corners_fast(image, threshold, Fast::Nine)
 }
 
+/// The score of a corner is the greatest threshold for which the given
+/// pixel is still a corner, found by bisection from `threshold` upwards.
 pub fn fast_corner_score(image: &GrayFloatImage, threshold: u8, x: u32, y: u32, variant: Fast) -> u8 {
     let mut max = 255u8;
     let mut min = threshold;
@@ -165,16 +281,468 @@ pub fn fast_corner_score(image: &GrayFloatImage, threshold: u8, x: u32, y: u32,
         let mean = ((max as u16 + min as u16) / 2u16) as u8;
         let probe = if max == min + 1 { max } else { mean };
 
-        let is_corner = match variant {
-            Fast::Nine => is_corner_fast9(image, probe, x, y),
-        };
-
-        println!("is_corner : {}", is_corner);
-
-        if is_corner {
+        if is_corner(image, probe, x, y, variant) {
             min = probe;
         } else {
             max = probe - 1;
         }
     }
 }
+
+
+/// Half-widths of the circular patch for each row offset `v` in `0..=half_patch_size`,
+/// made symmetric between rows and columns as in ORB-SLAM's `ORBextractor`.
+pub fn umax_table(half_patch_size: usize) -> Vec<i32> {
+    let hp = half_patch_size as f64;
+    let mut umax = vec![0i32; half_patch_size + 2];
+    let vmax = (hp * 2f64.sqrt() / 2.0 + 1.0).floor() as usize;
+    let vmin = (hp * 2f64.sqrt() / 2.0).ceil() as usize;
+
+    for (v, u) in umax.iter_mut().enumerate().take(vmax + 1) {
+        *u = (hp * hp - (v * v) as f64).sqrt().round() as i32;
+    }
+
+    let mut v0 = 0;
+    for v in (vmin..=half_patch_size).rev() {
+        while umax[v0] == umax[v0 + 1] {
+            v0 += 1;
+        }
+        umax[v] = v0 as i32;
+        v0 += 1;
+    }
+
+    umax.truncate(half_patch_size + 1);
+    umax
+}
+
+/// Orientation of the patch around `(x, y)` by the intensity centroid: the angle,
+/// in degrees in `[0, 360)`, of the vector from the center to the centroid of the
+/// circular patch described by `umax`. Pixels outside the image are clamped to the border.
+pub fn ic_angle(image: &GrayFloatImage, umax: &[i32], x: f32, y: f32) -> f32 {
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    let (cx, cy) = (x.round() as i32, y.round() as i32);
+    let pixel = |u: i32, v: i32| {
+        let px = (cx + u).clamp(0, width - 1);
+        let py = (cy + v).clamp(0, height - 1);
+        image.get(px as usize, py as usize)
+    };
+
+    let half = umax.len() as i32 - 1;
+    let (mut m_01, mut m_10) = (0f32, 0f32);
+
+    for u in -half..=half {
+        m_10 += u as f32 * pixel(u, 0);
+    }
+
+    for v in 1..=half {
+        let d = umax[v as usize];
+        let mut v_sum = 0f32;
+        for u in -d..=d {
+            let (val_plus, val_minus) = (pixel(u, v), pixel(u, -v));
+            v_sum += val_plus - val_minus;
+            m_10 += u as f32 * (val_plus + val_minus);
+        }
+        m_01 += v as f32 * v_sum;
+    }
+
+    let angle = m_01.atan2(m_10).to_degrees();
+    if angle < 0.0 {
+        angle + 360.0
+    } else {
+        angle
+    }
+}
+
+/// Sets the angle of every keypoint from the image it was detected on.
+pub fn compute_orientation(image: &GrayFloatImage, keypoints: &mut [KeyPoint]) {
+    let umax = umax_table(HALF_PATCH_SIZE);
+    for keypoint in keypoints.iter_mut() {
+        keypoint.angle = ic_angle(image, &umax, keypoint.x, keypoint.y);
+    }
+}
+
+/// Sets the angle of keypoints spread over a pyramid, reading each one from the
+/// level given by its `octave`.
+pub fn compute_pyramid_orientation(pyramid: &[GrayFloatImage], keypoints: &mut [KeyPoint]) {
+    let umax = umax_table(HALF_PATCH_SIZE);
+    for keypoint in keypoints.iter_mut() {
+        keypoint.angle = ic_angle(&pyramid[keypoint.octave], &umax, keypoint.x, keypoint.y);
+    }
+}
+
+/// 256-bit steered BRIEF descriptor.
+pub type OrbDescriptor = [u8; 32];
+
+/// Smoothing applied to a pyramid level before sampling BRIEF pairs, a 7x7
+/// Gaussian with sigma 2 as in ORB-SLAM.
+pub fn smooth_for_descriptors(image: &GrayFloatImage) -> GrayFloatImage {
+    gaussian_blur_with_size(image, 2.0, 7)
+}
+
+/// rBRIEF descriptor of `keypoint` on an already smoothed image: the learned
+/// sampling pattern is rotated by the keypoint angle and bit `i` is set when the
+/// first point of pair `i` is darker than the second. Samples falling outside the
+/// image are clamped to the border.
+pub fn compute_orb_descriptor(smoothed: &GrayFloatImage, keypoint: &KeyPoint) -> OrbDescriptor {
+    let (width, height) = (smoothed.width() as i32, smoothed.height() as i32);
+    let (cx, cy) = (keypoint.x.round() as i32, keypoint.y.round() as i32);
+    let angle = keypoint.angle.to_radians();
+    let (a, b) = (angle.cos(), angle.sin());
+
+    let value = |x: i8, y: i8| {
+        let (x, y) = (x as f32, y as f32);
+        let u = (x * a - y * b).round() as i32;
+        let v = (x * b + y * a).round() as i32;
+        let px = (cx + u).clamp(0, width - 1);
+        let py = (cy + v).clamp(0, height - 1);
+        smoothed.get(px as usize, py as usize)
+    };
+
+    let mut descriptor = [0u8; 32];
+    for (byte, pairs) in descriptor.iter_mut().zip(ORB_PATTERN.chunks(8)) {
+        for (bit, [x1, y1, x2, y2]) in pairs.iter().enumerate() {
+            if value(*x1, *y1) < value(*x2, *y2) {
+                *byte |= 1 << bit;
+            }
+        }
+    }
+
+    descriptor
+}
+
+/// Descriptors for keypoints detected on `image`, one per keypoint and in the same order.
+pub fn compute_descriptors(image: &GrayFloatImage, keypoints: &[KeyPoint]) -> Vec<OrbDescriptor> {
+    let smoothed = smooth_for_descriptors(image);
+    keypoints
+        .iter()
+        .map(|keypoint| compute_orb_descriptor(&smoothed, keypoint))
+        .collect()
+}
+
+/// Descriptors for keypoints spread over a pyramid, each one sampled on the smoothed
+/// level given by its `octave`. The output is aligned with `keypoints`.
+pub fn compute_pyramid_descriptors(pyramid: &[GrayFloatImage], keypoints: &[KeyPoint]) -> Vec<OrbDescriptor> {
+    let mut smoothed: Vec<Option<GrayFloatImage>> = vec![None; pyramid.len()];
+    keypoints
+        .iter()
+        .map(|keypoint| {
+            let level = smoothed[keypoint.octave]
+                .get_or_insert_with(|| smooth_for_descriptors(&pyramid[keypoint.octave]));
+            compute_orb_descriptor(level, keypoint)
+        })
+        .collect()
+}
+
+/// ORB's learned sampling pattern for a 31x31 patch, as `[x1, y1, x2, y2]` point
+/// pairs (Rublee et al., "ORB: an efficient alternative to SIFT or SURF").
+#[rustfmt::skip]
+const ORB_PATTERN: [[i8; 4]; 256] = [
+    [8, -3, 9, 5], [4, 2, 7, -12], [-11, 9, -8, 2], [7, -12, 12, -13],
+    [2, -13, 2, 12], [1, -7, 1, 6], [-2, -10, -2, -4], [-13, -13, -11, -8],
+    [-13, -3, -12, -9], [10, 4, 11, 9], [-13, -8, -8, -9], [-11, 7, -9, 12],
+    [7, 7, 12, 6], [-4, -5, -3, 0], [-13, 2, -12, -3], [-9, 0, -7, 5],
+    [12, -6, 12, -1], [-3, 6, -2, 12], [-6, -13, -4, -8], [11, -13, 12, -8],
+    [4, 7, 5, 1], [5, -3, 10, -3], [3, -7, 6, 12], [-8, -7, -6, -2],
+    [-2, 11, -1, -10], [-13, 12, -8, 10], [-7, 3, -5, -3], [-4, 2, -3, 7],
+    [-10, -12, -6, 11], [5, -12, 6, -7], [5, -6, 7, -1], [1, 0, 4, -5],
+    [9, 11, 11, -13], [4, 7, 4, 12], [2, -1, 4, 4], [-4, -12, -2, 7],
+    [-8, -5, -7, -10], [4, 11, 9, 12], [0, -8, 1, -13], [-13, -2, -8, 2],
+    [-3, -2, -2, 3], [-6, 9, -4, -9], [8, 12, 10, 7], [0, 9, 1, 3],
+    [7, -5, 11, -10], [-13, -6, -11, 0], [10, 7, 12, 1], [-6, -3, -6, 12],
+    [10, -9, 12, -4], [-13, 8, -8, -12], [-13, 0, -8, -4], [3, 3, 7, 8],
+    [5, 7, 10, -7], [-1, 7, 1, -12], [3, -10, 5, 6], [2, -4, 3, -10],
+    [-13, 0, -13, 5], [-13, -7, -12, 12], [-13, 3, -11, 8], [-7, 12, -4, 7],
+    [6, -10, 12, 8], [-9, -1, -7, -6], [-2, -5, 0, 12], [-12, 5, -7, 5],
+    [3, -10, 8, -13], [-7, -7, -4, 5], [-3, -2, -1, -7], [2, 9, 5, -11],
+    [-11, -13, -5, -13], [-1, 6, 0, -1], [5, -3, 5, 2], [-4, -13, -4, 12],
+    [-9, -6, -9, 6], [-12, -10, -8, -4], [10, 2, 12, -3], [7, 12, 12, 12],
+    [-7, -13, -6, 5], [-4, 9, -3, 4], [7, -1, 12, 2], [-7, 6, -5, 1],
+    [-13, 11, -12, 5], [-3, 7, -2, -6], [7, -8, 12, -7], [-13, -7, -11, -12],
+    [1, -3, 12, 12], [2, -6, 3, 0], [-4, 3, -2, -13], [-1, -13, 1, 9],
+    [7, 1, 8, -6], [1, -1, 3, 12], [9, 1, 12, 6], [-1, -9, -1, 3],
+    [-13, -13, -10, 5], [7, 7, 10, 12], [12, -5, 12, 9], [6, 3, 7, 11],
+    [5, -13, 6, 10], [2, -12, 2, 3], [3, 8, 4, -6], [2, 6, 12, -13],
+    [9, -12, 10, 3], [-8, 4, -7, 9], [-11, 12, -4, -6], [1, 12, 2, -8],
+    [6, -9, 7, -4], [2, 3, 3, -2], [6, 3, 11, 0], [3, -3, 8, -8],
+    [7, 8, 9, 3], [-11, -5, -6, -4], [-10, 11, -5, 10], [-5, -8, -3, 12],
+    [-10, 5, -9, 0], [8, -1, 12, -6], [4, -6, 6, -11], [-10, 12, -8, 7],
+    [4, -2, 6, 7], [-2, 0, -2, 12], [-5, -8, -5, 2], [7, -6, 10, 12],
+    [-9, -13, -8, -8], [-5, -13, -5, -2], [8, -8, 9, -13], [-9, -11, -9, 0],
+    [1, -8, 1, -2], [7, -4, 9, 1], [-2, 1, -1, -4], [11, -6, 12, -11],
+    [-12, -9, -6, 4], [3, 7, 7, 12], [5, 5, 10, 8], [0, -4, 2, 8],
+    [-9, 12, -5, -13], [0, 7, 2, 12], [-1, 2, 1, 7], [5, 11, 7, -9],
+    [3, 5, 6, -8], [-13, -4, -8, 9], [-5, 9, -3, -3], [-4, -7, -3, -12],
+    [6, 5, 8, 0], [-7, 6, -6, 12], [-13, 6, -5, -2], [1, -10, 3, 10],
+    [4, 1, 8, -4], [-2, -2, 2, -13], [2, -12, 12, 12], [-2, -13, 0, -6],
+    [4, 1, 9, 3], [-6, -10, -3, -5], [-3, -13, -1, 1], [7, 5, 12, -11],
+    [4, -2, 5, -7], [-13, 9, -9, -5], [7, 1, 8, 6], [7, -8, 7, 6],
+    [-7, -4, -7, 1], [-8, 11, -7, -8], [-13, 6, -12, -8], [2, 4, 3, 9],
+    [10, -5, 12, 3], [-6, -5, -6, 7], [8, -3, 9, -8], [2, -12, 2, 8],
+    [-11, -2, -10, 3], [-12, -13, -7, -9], [-11, 0, -10, -5], [5, -3, 11, 8],
+    [-2, -13, -1, 12], [-1, -8, 0, 9], [-13, -11, -12, -5], [-10, -2, -10, 11],
+    [-3, 9, -2, -13], [2, -3, 3, 2], [-9, -13, -4, 0], [-4, 6, -3, -10],
+    [-4, 12, -2, -7], [-6, -11, -4, 9], [6, -3, 6, 11], [-13, 11, -5, 5],
+    [11, 11, 12, 6], [7, -5, 12, -2], [-1, 12, 0, 7], [-4, -8, -3, -2],
+    [-7, 1, -6, 7], [-13, -12, -8, -13], [-7, -2, -6, -8], [-8, 5, -6, -9],
+    [-5, -1, -4, 5], [-13, 7, -8, 10], [1, 5, 5, -13], [1, 0, 10, -13],
+    [9, 12, 10, -1], [5, -8, 10, -9], [-1, 11, 1, -13], [-9, -3, -6, 2],
+    [-1, -10, 1, 12], [-13, 1, -8, -10], [8, -11, 10, -6], [2, -13, 3, -6],
+    [7, -13, 12, -9], [-10, -10, -5, -7], [-10, -8, -8, -13], [4, -6, 8, 5],
+    [3, 12, 8, -13], [-4, 2, -3, -3], [5, -13, 10, -12], [4, -13, 5, -1],
+    [-9, 9, -4, 3], [0, 3, 3, -9], [-12, 1, -6, 1], [3, 2, 4, -8],
+    [-10, -10, -10, 9], [8, -13, 12, 12], [-8, -12, -6, -5], [2, 2, 3, 7],
+    [10, 6, 11, -8], [6, 8, 8, -12], [-7, 10, -6, 5], [-3, -9, -3, 9],
+    [-1, -13, -1, 5], [-3, -7, -3, 4], [-8, -2, -8, 3], [4, 2, 12, 12],
+    [2, -5, 3, 11], [6, -9, 11, -13], [3, -1, 7, 12], [11, -1, 12, 4],
+    [-3, 0, -3, 6], [4, -11, 4, 12], [2, -4, 2, 1], [-10, -6, -8, 1],
+    [-13, 7, -11, 1], [-13, 12, -11, -13], [6, 0, 11, -13], [0, -1, 1, 4],
+    [-13, 3, -9, -2], [-9, 8, -6, -3], [-13, -6, -8, -2], [5, -9, 8, 10],
+    [2, 7, 3, -9], [-1, -6, -1, -1], [9, 5, 11, -2], [11, -3, 12, -8],
+    [3, 0, 3, 5], [-1, 4, 0, 10], [3, -6, 4, 5], [-13, 0, -10, 5],
+    [5, 8, 12, 11], [8, 9, 9, -6], [7, -4, 8, -12], [-10, 4, -10, 9],
+    [7, 3, 12, 4], [9, -7, 10, -2], [7, 0, 12, -2], [-1, -6, 0, -11],
+];
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use image::GrayImage;
+
+    use super::{
+        compute_descriptors, compute_orientation, compute_pyramid_orientation, float_corners_fast, float_corners_fast9, has_circular_arc,
+        intensity, is_corner, umax_table, Corner, Fast,
+    };
+    use crate::image::GrayFloatImage;
+    use crate::KeyPoint;
+
+    /// Plain FAST on an 8-bit image: reads the whole circle and tries every arc start.
+    fn reference_is_corner(image: &GrayImage, threshold: u8, x: u32, y: u32, variant: Fast) -> bool {
+        let c = image.get_pixel(x, y)[0] as i16;
+        let t = threshold as i16;
+        let circle: Vec<i16> = variant
+            .circle()
+            .iter()
+            .map(|(dx, dy)| image.get_pixel((x as i32 + dx) as u32, (y as i32 + dy) as u32)[0] as i16)
+            .collect();
+        let (n, arc) = (circle.len(), variant.arc_length());
+
+        (0..n).any(|start| {
+            (0..arc).all(|i| circle[(start + i) % n] > c + t)
+                || (0..arc).all(|i| circle[(start + i) % n] < c - t)
+        })
+    }
+
+    fn reference_corners(image: &GrayImage, threshold: u8, variant: Fast) -> Vec<Corner> {
+        let r = variant.radius();
+        let mut corners = vec![];
+        for y in r..image.height() - r {
+            for x in r..image.width() - r {
+                if reference_is_corner(image, threshold, x, y, variant) {
+                    let score = (threshold..=255)
+                        .take_while(|t| reference_is_corner(image, *t, x, y, variant))
+                        .last()
+                        .unwrap();
+                    corners.push(Corner::new(x, y, score as f32));
+                }
+            }
+        }
+        corners
+    }
+
+    /// The FAST-9 segment test as it was before the arc lookup tables: a linear
+    /// scan around the 16-pixel circle with `search_span`.
+    fn scanning_is_corner_fast9(image: &GrayFloatImage, threshold: u8, x: u32, y: u32) -> bool {
+        let (width, height) = image.dimensions();
+        if x < 3 || y < 3 || width <= x + 3 || height <= y + 3 {
+            return false;
+        }
+
+        let c = unsafe { intensity(image, x, y) };
+        let low_thresh: i16 = c - threshold as i16;
+        let high_thresh: i16 = c + threshold as i16;
+
+        let circle: Vec<i16> = Fast::Nine
+            .circle()
+            .iter()
+            .map(|(dx, dy)| unsafe { intensity(image, (x as i32 + dx) as u32, (y as i32 + dy) as u32) })
+            .collect();
+        let (p0, p4, p8, p12) = (circle[0], circle[4], circle[8], circle[12]);
+
+        let above = (p0 > high_thresh || p8 > high_thresh) && (p4 > high_thresh || p12 > high_thresh);
+        let below = (p0 < low_thresh || p8 < low_thresh) && (p4 < low_thresh || p12 < low_thresh);
+        if !above && !below {
+            return false;
+        }
+
+        (above && search_span(&circle, 9, |c| *c > high_thresh))
+            || (below && search_span(&circle, 9, |c| *c < low_thresh))
+    }
+
+    fn search_span<F>(circle: &[i16], length: u8, f: F) -> bool
+    where
+        F: Fn(&i16) -> bool,
+    {
+        let mut nb_ok = 0u8;
+        let mut nb_ok_start = None;
+
+        for c in circle.iter() {
+            if f(c) {
+                nb_ok += 1;
+                if nb_ok == length {
+                    return true;
+                }
+            } else {
+                if nb_ok_start.is_none() {
+                    nb_ok_start = Some(nb_ok);
+                }
+                nb_ok = 0;
+            }
+        }
+
+        nb_ok + nb_ok_start.unwrap_or(0) >= length
+    }
+
+    fn load_test_image() -> (GrayFloatImage, GrayImage) {
+        let dynamic = image::open("input-image/test1.png").expect("failed to open test image");
+        (GrayFloatImage::from_dynamic(&dynamic), dynamic.grayscale().to_luma8())
+    }
+
+    #[test]
+    fn circular_arc_wraps_around() {
+        assert!(has_circular_arc(0b1110_0000_0000_0011, 16, 5));
+        assert!(!has_circular_arc(0b1110_0000_0000_0011, 16, 6));
+        assert!(has_circular_arc(0xffff, 16, 12));
+        assert!(has_circular_arc(0b1101_1011, 8, 4));
+        assert!(!has_circular_arc(0b1101_1011, 8, 5));
+    }
+
+    #[test]
+    fn fast9_matches_reference_on_real_image() {
+        let (float_image, gray_image) = load_test_image();
+
+        for threshold in [7, 20] {
+            let corners = float_corners_fast9(&float_image, threshold);
+            let expected = reference_corners(&gray_image, threshold, Fast::Nine);
+
+            assert!(!expected.is_empty());
+            assert_eq!(corners, expected);
+        }
+    }
+
+    #[test]
+    fn fast_variants_match_reference_on_real_image() {
+        let (float_image, gray_image) = load_test_image();
+
+        for variant in [Fast::Twelve, Fast::Seven, Fast::Five] {
+            let corners = float_corners_fast(&float_image, 7, variant);
+            let expected = reference_corners(&gray_image, 7, variant);
+
+            assert!(!expected.is_empty(), "{:?}", variant);
+            assert_eq!(corners, expected, "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn fast9_threshold_uses_8bit_units() {
+        let mut image = GrayFloatImage::new(16, 16);
+        for y in 8..16 {
+            for x in 8..16 {
+                image.put(x, y, 30.0 / 255.0);
+            }
+        }
+
+        let corners = float_corners_fast9(&image, 20);
+        assert!(corners.contains(&Corner::new(8, 8, 29.0)));
+        assert!(float_corners_fast9(&image, 30).is_empty());
+    }
+
+    #[test]
+    fn umax_table_matches_orb_slam() {
+        assert_eq!(
+            umax_table(15),
+            vec![15, 15, 15, 15, 14, 14, 14, 13, 13, 12, 11, 10, 9, 8, 6, 3]
+        );
+    }
+
+    #[test]
+    fn orientation_points_towards_brighter_side() {
+        let ramp = |dx: f32, dy: f32| {
+            let mut image = GrayFloatImage::new(64, 64);
+            for y in 0..64 {
+                for x in 0..64 {
+                    image.put(x, y, 0.5 + (dx * (x as f32 - 32.0) + dy * (y as f32 - 32.0)) / 128.0);
+                }
+            }
+            image
+        };
+        let pyramid = vec![ramp(1.0, 0.0), ramp(0.0, 1.0), ramp(-1.0, -1.0)];
+
+        let corner = Corner::new(32, 32, 1.0);
+        let mut keypoints: Vec<KeyPoint> = (0..3).map(|octave| KeyPoint::from_corner(&corner, octave)).collect();
+        compute_pyramid_orientation(&pyramid, &mut keypoints);
+
+        for (keypoint, expected) in keypoints.iter().zip([0.0, 90.0, 225.0]) {
+            assert!((keypoint.angle - expected).abs() < 1.0, "{} != {}", keypoint.angle, expected);
+        }
+    }
+
+    #[test]
+    fn orb_descriptor_is_rotation_invariant() {
+        let size = 65;
+        let texture = crate::image::gaussian_blur(&crate::image::noise_image(size, 12345), 1.0);
+
+        let mut rotated = GrayFloatImage::new(size, size);
+        let last = size as usize - 1;
+        for y in 0..=last {
+            for x in 0..=last {
+                rotated.put(last - y, x, texture.get(x, y));
+            }
+        }
+
+        let corner = Corner::new(32, 32, 1.0);
+        let mut descriptors = vec![];
+        for image in [&texture, &rotated] {
+            let mut keypoints = vec![KeyPoint::from_corner(&corner, 0)];
+            compute_orientation(image, &mut keypoints);
+            descriptors.extend(compute_descriptors(image, &keypoints));
+        }
+
+        let distance: u32 = descriptors[0]
+            .iter()
+            .zip(descriptors[1].iter())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        assert!(distance < 32, "hamming distance {}", distance);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_fast9_arc_table_vs_span_scan() {
+        let (image, _) = load_test_image();
+        let (width, height) = image.dimensions();
+
+        let start = Instant::now();
+        let mut scanned = 0;
+        for y in 0..height {
+            for x in 0..width {
+                scanned += scanning_is_corner_fast9(&image, 20, x, y) as usize;
+            }
+        }
+        let scan_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut looked_up = 0;
+        for y in 0..height {
+            for x in 0..width {
+                looked_up += is_corner(&image, 20, x, y, Fast::Nine) as usize;
+            }
+        }
+        let table_time = start.elapsed();
+
+        println!("span scan : {:?} ({} corners)", scan_time, scanned);
+        println!("arc table : {:?} ({} corners)", table_time, looked_up);
+        assert_eq!(scanned, looked_up);
+    }
+}
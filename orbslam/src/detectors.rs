@@ -0,0 +1,332 @@
+use crate::descriptors::{
+    compute_descriptors, compute_orientation, fast_non_max_suppression, float_corners_fast, Fast, OrbDescriptor,
+};
+use crate::edlines::{edlines, EdLinesParams};
+use crate::harris::{non_maximum_suppression, Harris};
+use crate::image::{build_pyramid, GrayFloatImage};
+use crate::line::{merge_segments, remove_scale_duplicates, LineMergeParams, LineSegment, ScaleDuplicateParams};
+use crate::lsd::{new_lsd_detector, LsdParams};
+use crate::orb::OrbExtractor;
+use crate::KeyPoint;
+
+/// Finds keypoints in an image.
+pub trait FeatureDetector {
+    fn detect(&self, image: &GrayFloatImage) -> Vec<KeyPoint>;
+}
+
+/// Describes keypoints found by any [`FeatureDetector`].
+pub trait DescriptorExtractor {
+    type Descriptor;
+
+    /// One descriptor per keypoint, aligned with `keypoints`.
+    fn compute(&self, image: &GrayFloatImage, keypoints: &[KeyPoint]) -> Vec<Self::Descriptor>;
+
+    fn detect_and_compute<F: FeatureDetector + ?Sized>(
+        &self,
+        detector: &F,
+        image: &GrayFloatImage,
+    ) -> (Vec<KeyPoint>, Vec<Self::Descriptor>) {
+        let keypoints = detector.detect(image);
+        let descriptors = self.compute(image, &keypoints);
+        (keypoints, descriptors)
+    }
+}
+
+/// Finds line segments in an image.
+pub trait LineDetector {
+    fn detect_lines(&self, image: &GrayFloatImage) -> Vec<LineSegment>;
+}
+
+/// Describes line segments found by any [`LineDetector`].
+pub trait LineDescriptorExtractor {
+    type Descriptor;
+
+    /// One descriptor per segment, aligned with `lines`.
+    fn compute_lines(&self, image: &GrayFloatImage, lines: &[LineSegment]) -> Vec<Self::Descriptor>;
+
+    fn detect_and_compute_lines<L: LineDetector + ?Sized>(
+        &self,
+        detector: &L,
+        image: &GrayFloatImage,
+    ) -> (Vec<LineSegment>, Vec<Self::Descriptor>) {
+        let lines = detector.detect_lines(image);
+        let descriptors = self.compute_lines(image, &lines);
+        (lines, descriptors)
+    }
+}
+
+/// [`Harris::corner_detector`] with its parameters. Keypoints get the window as
+/// size and the Harris response.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HarrisDetector {
+    pub window_size: usize,
+    pub k: f32,
+    pub threshold: f32,
+}
+
+impl Default for HarrisDetector {
+    fn default() -> Self {
+        HarrisDetector {
+            window_size: 5,
+            k: 0.04,
+            threshold: 1.0,
+        }
+    }
+}
+
+impl FeatureDetector for HarrisDetector {
+    fn detect(&self, image: &GrayFloatImage) -> Vec<KeyPoint> {
+        let r = Harris::corner_response(image, self.window_size, self.k);
+        non_maximum_suppression(&r, image.width(), image.height(), self.threshold)
+            .into_iter()
+            .map(|(x, y)| KeyPoint {
+                size: self.window_size as f32,
+                response: r[[y, x]],
+                ..KeyPoint::from((x, y))
+            })
+            .collect()
+    }
+}
+
+/// Single scale FAST. `threshold` is in 8-bit intensity units.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FastDetector {
+    pub threshold: u8,
+    pub variant: Fast,
+    pub non_max_suppression: bool,
+}
+
+impl Default for FastDetector {
+    fn default() -> Self {
+        FastDetector {
+            threshold: 20,
+            variant: Fast::Nine,
+            non_max_suppression: true,
+        }
+    }
+}
+
+impl FeatureDetector for FastDetector {
+    fn detect(&self, image: &GrayFloatImage) -> Vec<KeyPoint> {
+        let mut corners = float_corners_fast(image, self.threshold, self.variant);
+        if self.non_max_suppression {
+            corners = fast_non_max_suppression(&corners, image.width() as u32, image.height() as u32);
+        }
+        corners.iter().map(|corner| KeyPoint::from_corner(corner, 0)).collect()
+    }
+}
+
+/// Single scale rBRIEF. When `oriented`, the keypoint angles are recomputed with the
+/// intensity centroid before sampling, which detectors without orientation need
+/// for rotation invariance.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RbriefExtractor {
+    pub oriented: bool,
+}
+
+impl Default for RbriefExtractor {
+    fn default() -> Self {
+        RbriefExtractor { oriented: true }
+    }
+}
+
+impl DescriptorExtractor for RbriefExtractor {
+    type Descriptor = OrbDescriptor;
+
+    fn compute(&self, image: &GrayFloatImage, keypoints: &[KeyPoint]) -> Vec<OrbDescriptor> {
+        if !self.oriented {
+            return compute_descriptors(image, keypoints);
+        }
+        let mut keypoints = keypoints.to_vec();
+        compute_orientation(image, &mut keypoints);
+        compute_descriptors(image, &keypoints)
+    }
+}
+
+impl FeatureDetector for OrbExtractor {
+    fn detect(&self, image: &GrayFloatImage) -> Vec<KeyPoint> {
+        OrbExtractor::detect(self, image)
+    }
+}
+
+impl DescriptorExtractor for OrbExtractor {
+    type Descriptor = OrbDescriptor;
+
+    fn compute(&self, image: &GrayFloatImage, keypoints: &[KeyPoint]) -> Vec<OrbDescriptor> {
+        self.describe(image, keypoints)
+    }
+}
+
+/// [`new_lsd_detector`] behind the common line detector interface.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LsdDetector {
+    pub params: LsdParams,
+    /// Link the fragments of long edges with [`merge_segments`] when set.
+    pub merge: Option<LineMergeParams>,
+}
+
+impl LineDetector for LsdDetector {
+    fn detect_lines(&self, image: &GrayFloatImage) -> Vec<LineSegment> {
+        let segments = new_lsd_detector(image, &self.params);
+        match &self.merge {
+            Some(params) => merge_segments(&segments, params),
+            None => segments,
+        }
+    }
+}
+
+/// [`edlines`] behind the common line detector interface, a faster alternative to
+/// [`LsdDetector`].
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct EdLinesDetector {
+    pub params: EdLinesParams,
+}
+
+impl LineDetector for EdLinesDetector {
+    fn detect_lines(&self, image: &GrayFloatImage) -> Vec<LineSegment> {
+        edlines(image, &self.params)
+    }
+}
+
+
+/// Runs `detector` on every level of an image pyramid built by
+/// [`build_pyramid`], so that edges too blurred or too large for a single scale
+/// are still found. Segments are returned in level 0 coordinates with the level
+/// they were found at as `octave`, and those found again on coarser levels are
+/// removed by [`remove_scale_duplicates`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PyramidLineDetector<D> {
+    pub detector: D,
+    pub n_levels: usize,
+    /// Scale between consecutive levels.
+    pub scale_factor: f32,
+    pub duplicates: ScaleDuplicateParams,
+}
+
+impl<D: LineDetector> PyramidLineDetector<D> {
+    pub fn new(detector: D) -> Self {
+        PyramidLineDetector {
+            detector,
+            n_levels: 4,
+            scale_factor: 1.5,
+            duplicates: ScaleDuplicateParams::default(),
+        }
+    }
+}
+
+impl<D: LineDetector> LineDetector for PyramidLineDetector<D> {
+    fn detect_lines(&self, image: &GrayFloatImage) -> Vec<LineSegment> {
+        let pyramid = build_pyramid(image, self.n_levels.max(1), self.scale_factor);
+        let segments: Vec<LineSegment> = pyramid
+            .iter()
+            .enumerate()
+            .flat_map(|(octave, level)| {
+                self.detector
+                    .detect_lines(level)
+                    .into_iter()
+                    .map(move |segment| LineSegment { octave, ..segment }.to_level_zero(self.scale_factor))
+            })
+            .collect();
+        remove_scale_duplicates(&segments, self.scale_factor, &self.duplicates)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{
+        DescriptorExtractor, FastDetector, FeatureDetector, HarrisDetector, LineDetector, LsdDetector,
+        PyramidLineDetector, RbriefExtractor,
+    };
+    use crate::image::{gaussian_blur, GrayFloatImage};
+    use crate::orb::{OrbExtractor, OrbSettings};
+
+    fn pipeline<F: FeatureDetector, E: DescriptorExtractor>(
+        detector: &F,
+        extractor: &E,
+        image: &GrayFloatImage,
+    ) -> usize {
+        let (keypoints, descriptors) = extractor.detect_and_compute(detector, image);
+        assert_eq!(keypoints.len(), descriptors.len());
+        keypoints.len()
+    }
+
+    #[test]
+    fn detectors_are_interchangeable() {
+        let image = GrayFloatImage::load_image("input-image/test1.png");
+
+        let harris = pipeline(&HarrisDetector::default(), &RbriefExtractor::default(), &image);
+        let fast = pipeline(&FastDetector::default(), &RbriefExtractor::default(), &image);
+        let orb = OrbExtractor::new(OrbSettings { n_features: 500, ..OrbSettings::default() });
+        let orb_count = pipeline(&orb, &orb, &image);
+        assert!(harris > 0 && fast > 0 && orb_count > 0);
+
+        let detectors: Vec<Box<dyn FeatureDetector>> = vec![Box::new(HarrisDetector::default()), Box::new(orb)];
+        for detector in detectors.iter() {
+            let keypoints = detector.detect(&image);
+            assert!(keypoints
+                .iter()
+                .all(|k| k.x >= 0.0 && k.y >= 0.0 && k.x < image.width() as f32 && k.y < image.height() as f32));
+        }
+    }
+
+    #[test]
+    fn orb_describe_matches_extract() {
+        let image = GrayFloatImage::load_image("input-image/test1.png");
+        let orb = OrbExtractor::new(OrbSettings { n_features: 300, ..OrbSettings::default() });
+
+        let (keypoints, descriptors) = orb.extract(&image);
+        assert_eq!(orb.compute(&image, &keypoints), descriptors);
+    }
+
+    #[test]
+    fn pyramid_lines_are_in_level_zero_coordinates() {
+        // A square with sharp edges and one with edges blurred over many pixels.
+        let mut sharp = GrayFloatImage::new(240, 240);
+        let mut blurred = GrayFloatImage::new(240, 240);
+        for y in 0..240 {
+            for x in 0..240 {
+                if (40..120).contains(&x) && (40..120).contains(&y) {
+                    sharp.put(x, y, 0.8);
+                }
+                if (130..220).contains(&x) && (130..220).contains(&y) {
+                    blurred.put(x, y, 0.8);
+                }
+            }
+        }
+        let blurred = gaussian_blur(&blurred, 6.0);
+        let mut image = GrayFloatImage::new(240, 240);
+        for y in 0..240 {
+            for x in 0..240 {
+                image.put(x, y, sharp.get(x, y) + blurred.get(x, y));
+            }
+        }
+
+        let mut detector = PyramidLineDetector::new(LsdDetector::default());
+        let lines = detector.detect_lines(&image);
+        assert_eq!(lines.len(), 8, "{:?}", lines);
+        detector.duplicates.min_coverage = f32::INFINITY;
+        let all = detector.detect_lines(&image);
+        assert!(all.iter().any(|l| l.octave == detector.n_levels - 1), "{:?}", all);
+        assert!(all.len() > lines.len());
+
+        // Every edge is found once, in level 0 coordinates.
+        for (x1, y1, x2, y2) in [
+            (39.5, 39.5, 119.5, 39.5),
+            (119.5, 39.5, 119.5, 119.5),
+            (129.5, 129.5, 219.5, 129.5),
+            (219.5, 129.5, 219.5, 219.5),
+        ] {
+            let found: Vec<_> = lines
+                .iter()
+                .filter(|l| {
+                    let (mx, my) = l.midpoint();
+                    l.length() > 30.0
+                        && ((x1 == x2 && (mx - x1).abs() < 3.0 && (y1..y2).contains(&my))
+                            || (y1 == y2 && (my - y1).abs() < 3.0 && (x1..x2).contains(&mx)))
+                })
+                .collect();
+            assert_eq!(found.len(), 1, "edge {:?} {:?}", (x1, y1, x2, y2), found);
+        }
+    }
+}
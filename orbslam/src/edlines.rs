@@ -0,0 +1,436 @@
+use std::f64::consts::PI;
+
+use ndarray::Array2;
+
+use crate::image::{gaussian_blur, sobel_filter_x, sobel_filter_y, GrayFloatImage};
+use crate::line::{fit_line, LineSegment};
+use crate::lsd::{is_aligned, nfa};
+
+/// Parameters of [`edlines`], with the defaults of the reference implementation.
+/// They rarely need tuning: the minimal line length and the validation threshold
+/// follow from the image size.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EdLinesParams {
+    /// Standard deviation of the Gaussian smoothing before edge drawing.
+    pub sigma: f32,
+    /// Pixels with a smaller Sobel gradient `|gx| + |gy|`, in 8-bit intensity
+    /// levels, are not edge pixels.
+    pub gradient_threshold: f32,
+    /// Anchors exceed both neighbours across the edge by this much.
+    pub anchor_threshold: f32,
+    /// Anchors are searched on every `scan_interval`-th row and column.
+    pub scan_interval: usize,
+    /// Maximal RMS distance of the chain pixels to a fitted line, in pixels.
+    pub line_fit_error: f32,
+    /// Angle tolerance in degrees of aligned pixels for the validation.
+    pub ang_th: f64,
+    /// Segments are kept when `-log10(NFA) > log_eps`, all segments are kept
+    /// without validation when `None`.
+    pub log_eps: Option<f64>,
+}
+
+impl Default for EdLinesParams {
+    fn default() -> Self {
+        EdLinesParams {
+            sigma: 1.0,
+            gradient_threshold: 36.0,
+            anchor_threshold: 8.0,
+            scan_interval: 1,
+            line_fit_error: 1.0,
+            ang_th: 22.5,
+            log_eps: Some(0.0),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum EdgeDirection {
+    Horizontal,
+    Vertical,
+}
+
+/// Sobel gradient magnitude, zero below the threshold and on the border, and the
+/// direction of the edge through each pixel.
+struct GradientMap {
+    magnitude: Array2<f32>,
+    direction: Array2<EdgeDirection>,
+}
+
+impl GradientMap {
+    fn new(image: &GrayFloatImage, params: &EdLinesParams) -> GradientMap {
+        let smoothed = gaussian_blur(image, params.sigma);
+        let (i_x, i_y) = (sobel_filter_x(&smoothed), sobel_filter_y(&smoothed));
+        let magnitude = ndarray::Zip::from(&i_x).and(&i_y).map_collect(|gx, gy| {
+            let g = 255.0 * (gx.abs() + gy.abs());
+            if g >= params.gradient_threshold {
+                g
+            } else {
+                0.0
+            }
+        });
+        let direction = ndarray::Zip::from(&i_x).and(&i_y).map_collect(|gx, gy| {
+            if gx.abs() >= gy.abs() {
+                EdgeDirection::Vertical
+            } else {
+                EdgeDirection::Horizontal
+            }
+        });
+        GradientMap { magnitude, direction }
+    }
+
+    fn at(&self, x: isize, y: isize) -> f32 {
+        let (height, width) = self.magnitude.dim();
+        if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+            return 0.0;
+        }
+        self.magnitude[[y as usize, x as usize]]
+    }
+
+    /// Anchors are local maxima of the gradient across the edge, strongest first.
+    fn anchors(&self, params: &EdLinesParams) -> Vec<(usize, usize)> {
+        let (height, width) = self.magnitude.dim();
+        let step = params.scan_interval.max(1);
+        let mut anchors = vec![];
+        for y in (1..height.saturating_sub(1)).step_by(step) {
+            for x in (1..width.saturating_sub(1)).step_by(step) {
+                let g = self.magnitude[[y, x]];
+                if g == 0.0 {
+                    continue;
+                }
+                let (a, b) = match self.direction[[y, x]] {
+                    EdgeDirection::Horizontal => (self.magnitude[[y - 1, x]], self.magnitude[[y + 1, x]]),
+                    EdgeDirection::Vertical => (self.magnitude[[y, x - 1]], self.magnitude[[y, x + 1]]),
+                };
+                if g - a >= params.anchor_threshold && g - b >= params.anchor_threshold {
+                    anchors.push((x, y));
+                }
+            }
+        }
+        anchors.sort_by(|a, b| self.magnitude[[b.1, b.0]].total_cmp(&self.magnitude[[a.1, a.0]]));
+        anchors
+    }
+}
+
+/// Edge Drawing (Topal and Akinlar, "Edge Drawing: a combined real-time edge and
+/// segment detector"): edges are drawn from anchors by following the ridge of the
+/// gradient magnitude. Returns one chain of 8-connected pixels per anchor that
+/// started a new edge, as `(x, y)`.
+pub fn edge_drawing(image: &GrayFloatImage, params: &EdLinesParams) -> Vec<Vec<(usize, usize)>> {
+    let gradients = GradientMap::new(image, params);
+    let mut edges = Array2::from_elem(gradients.magnitude.dim(), false);
+
+    let mut chains = vec![];
+    for (x, y) in gradients.anchors(params) {
+        if edges[[y, x]] {
+            continue;
+        }
+        edges[[y, x]] = true;
+        let (backward, forward) = match gradients.direction[[y, x]] {
+            EdgeDirection::Horizontal => ((-1, 0), (1, 0)),
+            EdgeDirection::Vertical => ((0, -1), (0, 1)),
+        };
+
+        let mut chain = walk(&gradients, &mut edges, (x, y), backward);
+        chain.reverse();
+        chain.push((x, y));
+        chain.extend(walk(&gradients, &mut edges, (x, y), forward));
+        if chain.len() > 1 {
+            chains.push(chain);
+        }
+    }
+    chains
+}
+
+/// Follows the edge from `start` heading `(hx, hy)`, one of the four axis
+/// directions, moving to the strongest of the three pixels ahead. The heading
+/// turns when the edge direction changes and the walk stops on weak gradients or
+/// on pixels that already belong to an edge.
+fn walk(gradients: &GradientMap, edges: &mut Array2<bool>, start: (usize, usize), heading: (isize, isize)) -> Vec<(usize, usize)> {
+    let (mut x, mut y) = (start.0 as isize, start.1 as isize);
+    let (mut hx, mut hy) = heading;
+    let mut previous = None;
+    let mut pixels = vec![];
+
+    loop {
+        // The pixel straight ahead and its two neighbours across the heading. After
+        // a turn the previous pixel can be one of them.
+        let ahead: Vec<(isize, isize)> = [(x + hx, y + hy), (x + hx - hy, y + hy - hx), (x + hx + hy, y + hy + hx)]
+            .into_iter()
+            .filter(|p| Some(*p) != previous)
+            .collect();
+        if ahead.iter().any(|&(ax, ay)| gradients.at(ax, ay) > 0.0 && edges[[ay as usize, ax as usize]]) {
+            break;
+        }
+        let &(nx, ny) = ahead.iter().max_by(|a, b| gradients.at(a.0, a.1).total_cmp(&gradients.at(b.0, b.1))).unwrap();
+        if gradients.at(nx, ny) == 0.0 {
+            break;
+        }
+
+        let (ux, uy) = (nx as usize, ny as usize);
+        edges[[uy, ux]] = true;
+        pixels.push((ux, uy));
+
+        let moving_horizontally = hy == 0;
+        match (gradients.direction[[uy, ux]], moving_horizontally) {
+            (EdgeDirection::Vertical, true) => {
+                // Turn up or down, towards the stronger side.
+                let (up, down) = (gradients.at(nx, ny - 1), gradients.at(nx, ny + 1));
+                (hx, hy) = (0, if up > down { -1 } else { 1 });
+            }
+            (EdgeDirection::Horizontal, false) => {
+                let (left, right) = (gradients.at(nx - 1, ny), gradients.at(nx + 1, ny));
+                (hx, hy) = (if left > right { -1 } else { 1 }, 0);
+            }
+            _ => {}
+        }
+        previous = Some((x, y));
+        (x, y) = (nx, ny);
+    }
+
+    pixels
+}
+
+/// Splits a chain into the pieces well fitted by lines: a [`fit_line`] line is
+/// fitted to the first `min_length` pixels, sliding forward while its RMS error
+/// exceeds `max_error`, then extended pixel by pixel while they stay within
+/// `max_error` of it, and refitted to the whole piece.
+fn fit_lines(chain: &[(usize, usize)], min_length: usize, max_error: f32) -> Vec<LineSegment> {
+    let points: Vec<(f32, f32)> = chain.iter().map(|&(x, y)| (x as f32, y as f32)).collect();
+    let rms_error = |line: &LineSegment, points: &[(f32, f32)]| {
+        (points.iter().map(|&(x, y)| line.line_distance(x, y).powi(2)).sum::<f32>() / points.len() as f32).sqrt()
+    };
+
+    let mut lines = vec![];
+    let mut start = 0;
+    while points.len() - start >= min_length.max(2) {
+        let initial = &points[start..start + min_length.max(2)];
+        let Some(line) = fit_line(initial, None).filter(|line| rms_error(line, initial) <= max_error) else {
+            start += 1;
+            continue;
+        };
+
+        let mut end = start + initial.len();
+        while end < points.len() && line.line_distance(points[end].0, points[end].1) <= max_error {
+            end += 1;
+        }
+
+        lines.extend(fit_line(&points[start..end], None));
+        start = end;
+    }
+    lines
+}
+
+/// EDLines (Akinlar and Topal, "EDLines: a real-time line segment detector with a
+/// false detection control"): edge chains from [`edge_drawing`] are split into
+/// least-squares line pieces of at least the minimal meaningful length, which are
+/// validated with the Helmholtz principle: the number of pixels along the segment
+/// whose level-line angle agrees with it must be unlikely in noise.
+///
+/// Segments are returned in `image` coordinates, oriented like those of
+/// [`crate::lsd::new_lsd_detector`] with the brighter side on the left of
+/// `(x1, y1) -> (x2, y2)` as displayed with y pointing down, and have a width of
+/// one pixel.
+pub fn edlines(image: &GrayFloatImage, params: &EdLinesParams) -> Vec<LineSegment> {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let prec = PI * params.ang_th / 180.0;
+    let p = params.ang_th / 180.0;
+    // N^4 segments in an N x N image, for both orientations of each segment.
+    let log_nt = 2.0 * (width.log10() + height.log10()) + 2f64.log10();
+    let min_length = ((-log_nt / p.log10()).round() as usize).max(2);
+
+    // LSD's gradient threshold for a quantization error of 2 levels.
+    let threshold = (2.0 / 255.0 / prec.sin()) as f32;
+
+    let mut segments = vec![];
+    for chain in edge_drawing(image, params) {
+        for line in fit_lines(&chain, min_length, params.line_fit_error) {
+            let (forward, backward) = count_aligned(image, threshold, &line, prec);
+            let (n, k, line) = if forward.1 >= backward.1 {
+                (forward.0, forward.1, line)
+            } else {
+                (backward.0, backward.1, line.reversed())
+            };
+
+            let log_nfa = nfa(n, k, p, log_nt);
+            if params.log_eps.is_some_and(|log_eps| log_nfa <= log_eps) {
+                continue;
+            }
+            segments.push(LineSegment { width: 1.0, log_nfa: log_nfa as f32, ..line });
+        }
+    }
+    segments
+}
+
+/// Level-line angle of the 2x2 gradient at pixel `(x, y)`, as in LSD, or `None`
+/// when the gradient is below `threshold` or the window leaves the image.
+fn level_line_angle(image: &GrayFloatImage, x: usize, y: usize, threshold: f32) -> Option<f64> {
+    if x + 1 >= image.width() || y + 1 >= image.height() {
+        return None;
+    }
+    let com1 = image.get(x + 1, y + 1) - image.get(x, y);
+    let com2 = image.get(x + 1, y) - image.get(x, y + 1);
+    let (gx, gy) = (com1 + com2, com1 - com2);
+    if ((gx * gx + gy * gy) / 4.0).sqrt() <= threshold {
+        return None;
+    }
+    Some((gx as f64).atan2(-gy as f64))
+}
+
+/// Number of pixels sampled along the segment at unit spacing and of those
+/// aligned with it, for both orientations of the segment. Angles are computed
+/// only where needed, which is much cheaper than LSD's full angle field.
+fn count_aligned(image: &GrayFloatImage, threshold: f32, line: &LineSegment, prec: f64) -> ((usize, usize), (usize, usize)) {
+    let start = (line.x1 as f64, line.y1 as f64);
+    let (dx, dy) = (line.x2 as f64 - start.0, line.y2 as f64 - start.1);
+    let length = dx.hypot(dy);
+    let theta = dy.atan2(dx);
+    let samples = length.round() as usize + 1;
+
+    let (mut n, mut forward, mut backward) = (0, 0, 0);
+    for i in 0..samples {
+        let t = if samples > 1 { i as f64 / (samples - 1) as f64 } else { 0.0 };
+        // The 2x2 gradient of pixel (x, y) is centered at (x + 0.5, y + 0.5).
+        let (x, y) = ((start.0 + t * dx - 0.5).round(), (start.1 + t * dy - 0.5).round());
+        if x < 0.0 || y < 0.0 || x as usize >= image.width() || y as usize >= image.height() {
+            continue;
+        }
+        n += 1;
+        let Some(angle) = level_line_angle(image, x as usize, y as usize, threshold) else {
+            continue;
+        };
+        if is_aligned(angle, theta, prec) {
+            forward += 1;
+        } else if is_aligned(angle, theta + PI, prec) {
+            backward += 1;
+        }
+    }
+    ((n, forward), (n, backward))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{edge_drawing, edlines, fit_lines, EdLinesParams, GradientMap};
+    use crate::image::{gaussian_blur, noise_image, GrayFloatImage};
+
+    /// Slightly blurred bright half-plane above the line `y = 0.5 x + 40`.
+    fn diagonal() -> GrayFloatImage {
+        let mut image = GrayFloatImage::new(160, 160);
+        for y in 0..160 {
+            for x in 0..160 {
+                image.put(x, y, if (y as f32) < 0.5 * x as f32 + 40.0 { 0.8 } else { 0.2 });
+            }
+        }
+        gaussian_blur(&image, 1.0)
+    }
+
+    fn diagonal_distance(x: f32, y: f32) -> f32 {
+        // The step lies halfway between the last bright and the first dark pixel.
+        (0.5 * x - y + 39.5).abs() / 1.25f32.sqrt()
+    }
+
+    /// Bright disk of radius 50 centered at (80, 80).
+    fn disk() -> GrayFloatImage {
+        let mut image = GrayFloatImage::new(160, 160);
+        for y in 0..160 {
+            for x in 0..160 {
+                let inside = (x as f32 - 80.0).hypot(y as f32 - 80.0) < 50.0;
+                image.put(x, y, if inside { 0.8 } else { 0.2 });
+            }
+        }
+        image
+    }
+
+    fn is_8_connected(chain: &[(usize, usize)]) -> bool {
+        chain.windows(2).all(|w| w[0] != w[1] && w[0].0.abs_diff(w[1].0) <= 1 && w[0].1.abs_diff(w[1].1) <= 1)
+    }
+
+    #[test]
+    fn anchors_lie_on_the_edge() {
+        let params = EdLinesParams::default();
+        let gradients = GradientMap::new(&diagonal(), &params);
+        let anchors = gradients.anchors(&params);
+        assert!(anchors.len() > 50, "{}", anchors.len());
+        for &(x, y) in anchors.iter() {
+            assert!(diagonal_distance(x as f32, y as f32) < 1.5, "{:?}", (x, y));
+        }
+        let strength: Vec<f32> = anchors.iter().map(|&(x, y)| gradients.magnitude[[y, x]]).collect();
+        assert!(strength.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn edge_drawing_links_a_diagonal_edge_into_one_chain() {
+        let chains = edge_drawing(&diagonal(), &EdLinesParams::default());
+        assert_eq!(chains.len(), 1, "{:?}", chains);
+        let chain = &chains[0];
+        assert!(is_8_connected(chain));
+        assert!(chain.iter().all(|&(x, y)| diagonal_distance(x as f32, y as f32) < 1.5));
+        // From border to border, with a diagonal step about every other pixel.
+        let (first, last) = (chain[0].0.min(chain[chain.len() - 1].0), chain[0].0.max(chain[chain.len() - 1].0));
+        assert!(first < 3 && last > 156, "{} {}", first, last);
+        let diagonal_steps = chain.windows(2).filter(|w| w[0].0 != w[1].0 && w[0].1 != w[1].1).count();
+        assert!(diagonal_steps > 60, "{}", diagonal_steps);
+    }
+
+    #[test]
+    fn edge_drawing_follows_a_circle() {
+        let chains = edge_drawing(&disk(), &EdLinesParams::default());
+        let total: usize = chains.iter().map(|c| c.len()).sum();
+        // A digital circle of radius 50 has about 4 * 50 * sqrt(2) pixels.
+        assert!(chains.len() <= 2 && total > 250, "{} chains, {} pixels", chains.len(), total);
+        for chain in chains.iter() {
+            assert!(is_8_connected(chain));
+            assert!(chain.iter().all(|&(x, y)| ((x as f32 - 80.0).hypot(y as f32 - 80.0) - 49.5).abs() < 1.5));
+        }
+    }
+
+    #[test]
+    fn fit_lines_splits_at_corners() {
+        let chain: Vec<(usize, usize)> = (0..40).map(|x| (x, 10)).chain((11..40).map(|y| (39, y))).collect();
+        let lines = fit_lines(&chain, 10, 1.0);
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        let line = lines[0];
+        assert!((line.y1 - 10.0).abs() < 0.5 && (line.y2 - 10.0).abs() < 0.5, "{:?}", line);
+        assert!(line.x1 < 1.0 && line.x2 > 37.0, "{:?}", line);
+    }
+
+    #[test]
+    fn circles_become_short_chords() {
+        // The chords are too short to be meaningful, so they are only kept without validation.
+        let segments = edlines(&disk(), &EdLinesParams { log_eps: None, ..EdLinesParams::default() });
+        assert!(segments.len() >= 12, "{:?}", segments);
+        for segment in segments.iter() {
+            assert!(segment.length() < 40.0, "{:?}", segment);
+            for (x, y) in [(segment.x1, segment.y1), (segment.x2, segment.y2)] {
+                assert!(((x - 80.0).hypot(y - 80.0) - 49.5).abs() < 1.5, "{:?}", segment);
+            }
+        }
+    }
+
+    #[test]
+    fn detects_a_diagonal_edge() {
+        let segments = edlines(&diagonal(), &EdLinesParams::default());
+        // The chain can be split in a few collinear pieces, which together span the edge.
+        assert!(!segments.is_empty() && segments.len() <= 3, "{:?}", segments);
+        assert!(segments.iter().map(|s| s.length()).sum::<f32>() > 150.0, "{:?}", segments);
+        for segment in segments.iter() {
+            assert!(segment.log_nfa > 10.0, "{:?}", segment);
+            assert!(diagonal_distance(segment.x1, segment.y1) < 0.5 && diagonal_distance(segment.x2, segment.y2) < 0.5);
+            // Brighter side, above the edge, on the left of the direction: it runs right.
+            assert!(segment.x2 > segment.x1, "{:?}", segment);
+        }
+    }
+
+    #[test]
+    fn validation_can_be_disabled() {
+        let image = noise_image(256, 99);
+        let validated = edlines(&image, &EdLinesParams::default());
+        assert!(validated.len() <= 1, "{:?}", validated);
+
+        // Without validation every line piece of the noise edges is kept, with its NFA.
+        let all = edlines(&image, &EdLinesParams { log_eps: None, ..EdLinesParams::default() });
+        assert!(all.len() > 10 * validated.len().max(1), "{}", all.len());
+        assert!(all.iter().any(|s| s.log_nfa <= 0.0));
+        assert!(validated.iter().all(|s| all.contains(s)));
+    }
+}
@@ -0,0 +1,435 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::detectors::{DescriptorExtractor, FeatureDetector};
+use crate::image::GrayFloatImage;
+use crate::matcher::{match_descriptors, Descriptor};
+use crate::KeyPoint;
+
+/// A 3x3 homography in row-major order, mapping image 1 points to image 2.
+pub type Homography = [[f32; 3]; 3];
+
+pub const CSV_HEADER: &str =
+    "transform,keypoints1,keypoints2,correspondences,repeatability,localization_error,correct_matches,matching_score";
+
+pub const PR_CSV_HEADER: &str = "transform,threshold,precision,recall";
+
+pub fn identity() -> Homography {
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+}
+
+/// `a * b`, i.e. `b` applied first.
+pub fn multiply(a: &Homography, b: &Homography) -> Homography {
+    let mut product = [[0.0; 3]; 3];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+/// Inverse of `h`, `None` when `h` is singular. The determinant is compared to
+/// the product of the row norms, its bound for orthogonal rows, so the test does
+/// not depend on the scale of `h`.
+pub fn invert(h: &Homography) -> Option<Homography> {
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| h[r0][c0] * h[r1][c1] - h[r0][c1] * h[r1][c0];
+    let adjugate = [
+        [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+        [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+        [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+    ];
+    let det: f32 = (0..3).map(|k| h[0][k] * adjugate[k][0]).sum();
+    let bound: f32 = h.iter().map(|row| row.iter().map(|v| v * v).sum::<f32>().sqrt()).product();
+    if det.abs() <= f32::EPSILON * bound {
+        return None;
+    }
+    Some(adjugate.map(|row| row.map(|v| v / det)))
+}
+
+/// Image of `(x, y)` under `h`, `None` for points mapped to infinity.
+pub fn project(h: &Homography, x: f32, y: f32) -> Option<(f32, f32)> {
+    let w = h[2][0] * x + h[2][1] * y + h[2][2];
+    if w.abs() < f32::EPSILON {
+        return None;
+    }
+    Some(((h[0][0] * x + h[0][1] * y + h[0][2]) / w, (h[1][0] * x + h[1][1] * y + h[1][2]) / w))
+}
+
+/// Rotation by `angle` degrees about `(cx, cy)`.
+pub fn rotation_about(angle: f32, cx: f32, cy: f32) -> Homography {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    [
+        [cos, -sin, cx - cos * cx + sin * cy],
+        [sin, cos, cy - sin * cx - cos * cy],
+        [0.0, 0.0, 1.0],
+    ]
+}
+
+/// Isotropic scaling by `scale` about `(cx, cy)`.
+pub fn scaling_about(scale: f32, cx: f32, cy: f32) -> Homography {
+    [[scale, 0.0, cx * (1.0 - scale)], [0.0, scale, cy * (1.0 - scale)], [0.0, 0.0, 1.0]]
+}
+
+/// Reads a homography stored as nine whitespace separated numbers, as in the
+/// Oxford affine and HPatches datasets. Singular matrices are rejected since
+/// [`warp_perspective`] and [`evaluate_features`] need the inverse.
+pub fn read_homography<P: AsRef<Path>>(path: P) -> io::Result<Homography> {
+    let values: Vec<f32> = fs::read_to_string(path)?
+        .split_whitespace()
+        .map(|v| v.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad homography value")))
+        .collect::<io::Result<_>>()?;
+    if values.len() != 9 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "a homography has nine values"));
+    }
+    let h = [
+        [values[0], values[1], values[2]],
+        [values[3], values[4], values[5]],
+        [values[6], values[7], values[8]],
+    ];
+    if invert(&h).is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the homography is not invertible"));
+    }
+    Ok(h)
+}
+
+/// Warps `image` by `h` into a `width` x `height` image; pixels mapped from
+/// outside the source are black.
+///
+/// # Panics
+///
+/// If `h` is not invertible.
+pub fn warp_perspective(image: &GrayFloatImage, h: &Homography, width: u32, height: u32) -> GrayFloatImage {
+    let inverse = invert(h).expect("homography is not invertible");
+    let (max_x, max_y) = ((image.width() - 1) as f32, (image.height() - 1) as f32);
+
+    let mut warped = GrayFloatImage::new(width, height);
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            if let Some((sx, sy)) = project(&inverse, x as f32, y as f32) {
+                if (0.0..=max_x).contains(&sx) && (0.0..=max_y).contains(&sy) {
+                    warped.put(x, y, image.sample_bilinear(sx, sy));
+                }
+            }
+        }
+    }
+    warped
+}
+
+/// Synthetic transformations of a `width` x `height` image used when no ground
+/// truth pair is given: rotations, scale changes and a mild perspective change,
+/// all about the image center.
+pub fn synthetic_homographies(width: u32, height: u32) -> Vec<(String, Homography)> {
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let mut homographies = vec![];
+    for angle in [10.0, 20.0, 45.0, 90.0] {
+        homographies.push((format!("rotation_{}", angle), rotation_about(angle, cx, cy)));
+    }
+    for scale in [0.85, 0.7, 0.5] {
+        homographies.push((format!("scale_{}", scale), scaling_about(scale, cx, cy)));
+    }
+    homographies.push((
+        "rotation_30_scale_0.8".to_string(),
+        multiply(&scaling_about(0.8, cx, cy), &rotation_about(30.0, cx, cy)),
+    ));
+    let tilt = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.2 / width as f32, 0.1 / height as f32, 1.0]];
+    homographies.push((
+        "perspective".to_string(),
+        multiply(&scaling_about(1.0 / 1.15, 0.0, 0.0), &tilt),
+    ));
+    homographies
+}
+
+/// Tunables of [`evaluate`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EvaluationParams {
+    /// A projected keypoint within this many pixels of a keypoint of the other
+    /// image is a correspondence.
+    pub max_distance: f32,
+    /// Number of descriptor distance thresholds of the precision/recall curve.
+    pub pr_steps: usize,
+}
+
+impl Default for EvaluationParams {
+    fn default() -> Self {
+        EvaluationParams {
+            max_distance: 2.5,
+            pr_steps: 20,
+        }
+    }
+}
+
+/// A point of the precision/recall curve: nearest neighbour matches with a
+/// descriptor distance up to `threshold`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PrPoint {
+    pub threshold: f32,
+    pub precision: f32,
+    pub recall: f32,
+}
+
+/// Detector and descriptor quality on an image pair, following Mikolajczyk et al.
+/// ("A comparison of affine region detectors", "A performance evaluation of local
+/// descriptors"). Keypoint counts only include keypoints visible in both images.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Evaluation {
+    pub keypoints1: usize,
+    pub keypoints2: usize,
+    /// One-to-one keypoint pairs closer than `max_distance` after projection.
+    pub correspondences: usize,
+    /// `correspondences / min(keypoints1, keypoints2)`.
+    pub repeatability: f32,
+    /// Mean projection error of the correspondences, in pixels.
+    pub localization_error: f32,
+    /// Nearest neighbour descriptor matches that are geometrically correct.
+    pub correct_matches: usize,
+    /// `correct_matches / min(keypoints1, keypoints2)`.
+    pub matching_score: f32,
+    pub pr_curve: Vec<PrPoint>,
+}
+
+impl Evaluation {
+    pub fn csv_row(&self, label: &str) -> String {
+        format!(
+            "{},{},{},{},{:.4},{:.4},{},{:.4}",
+            label,
+            self.keypoints1,
+            self.keypoints2,
+            self.correspondences,
+            self.repeatability,
+            self.localization_error,
+            self.correct_matches,
+            self.matching_score
+        )
+    }
+
+    pub fn pr_csv_rows(&self, label: &str) -> Vec<String> {
+        self.pr_curve
+            .iter()
+            .map(|p| format!("{},{},{:.4},{:.4}", label, p.threshold, p.precision, p.recall))
+            .collect()
+    }
+}
+
+/// Runs `detector` and `extractor` on both images and evaluates them against the
+/// ground truth `h` from `image1` to `image2`.
+///
+/// # Panics
+///
+/// If `h` is not invertible.
+pub fn evaluate<F, E>(
+    detector: &F,
+    extractor: &E,
+    image1: &GrayFloatImage,
+    image2: &GrayFloatImage,
+    h: &Homography,
+    params: &EvaluationParams,
+) -> Evaluation
+where
+    F: FeatureDetector + ?Sized,
+    E: DescriptorExtractor,
+    E::Descriptor: Descriptor,
+{
+    let (keypoints1, descriptors1) = extractor.detect_and_compute(detector, image1);
+    let (keypoints2, descriptors2) = extractor.detect_and_compute(detector, image2);
+    evaluate_features(
+        (&keypoints1, &descriptors1, image1),
+        (&keypoints2, &descriptors2, image2),
+        h,
+        params,
+    )
+}
+
+/// [`evaluate`] on features that were already extracted, given with the image
+/// they come from.
+///
+/// # Panics
+///
+/// If `h` is not invertible.
+pub fn evaluate_features<D: Descriptor>(
+    (keypoints1, descriptors1, image1): (&[KeyPoint], &[D], &GrayFloatImage),
+    (keypoints2, descriptors2, image2): (&[KeyPoint], &[D], &GrayFloatImage),
+    h: &Homography,
+    params: &EvaluationParams,
+) -> Evaluation {
+    let inverse = invert(h).expect("homography is not invertible");
+    let inside = |image: &GrayFloatImage, p: Option<(f32, f32)>| {
+        p.filter(|(x, y)| *x >= 0.0 && *y >= 0.0 && *x < image.width() as f32 && *y < image.height() as f32)
+    };
+
+    // Projections of the image 1 keypoints seen in image 2, and whether the image 2
+    // keypoints are seen in image 1.
+    let projected: Vec<Option<(f32, f32)>> = keypoints1
+        .iter()
+        .map(|k| inside(image2, project(h, k.x, k.y)))
+        .collect();
+    let visible2: Vec<bool> = keypoints2
+        .iter()
+        .map(|k| inside(image1, project(&inverse, k.x, k.y)).is_some())
+        .collect();
+    let common1 = projected.iter().filter(|p| p.is_some()).count();
+    let common2 = visible2.iter().filter(|v| **v).count();
+    let common = common1.min(common2).max(1) as f32;
+
+    let error = |i: usize, j: usize| {
+        projected[i].map(|(x, y)| ((x - keypoints2[j].x).powi(2) + (y - keypoints2[j].y).powi(2)).sqrt())
+    };
+
+    let mut candidates = vec![];
+    for i in 0..keypoints1.len() {
+        for (j, _) in visible2.iter().enumerate().filter(|(_, v)| **v) {
+            if let Some(e) = error(i, j).filter(|e| *e <= params.max_distance) {
+                candidates.push((e, i, j));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let (mut used1, mut used2) = (vec![false; keypoints1.len()], vec![false; keypoints2.len()]);
+    let (mut correspondences, mut total_error) = (0, 0.0);
+    for (e, i, j) in candidates {
+        if !used1[i] && !used2[j] {
+            used1[i] = true;
+            used2[j] = true;
+            correspondences += 1;
+            total_error += e;
+        }
+    }
+
+    let mut matches: Vec<(f32, bool)> = match_descriptors(descriptors1, descriptors2)
+        .into_iter()
+        .filter(|m| projected[m.query_idx].is_some() && visible2[m.train_idx])
+        .map(|m| (m.distance, error(m.query_idx, m.train_idx).is_some_and(|e| e <= params.max_distance)))
+        .collect();
+    matches.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let correct_matches = matches.iter().filter(|(_, correct)| *correct).count();
+
+    Evaluation {
+        keypoints1: common1,
+        keypoints2: common2,
+        correspondences,
+        repeatability: correspondences as f32 / common,
+        localization_error: if correspondences > 0 { total_error / correspondences as f32 } else { 0.0 },
+        correct_matches,
+        matching_score: correct_matches as f32 / common,
+        pr_curve: pr_curve(&matches, correspondences, params.pr_steps),
+    }
+}
+
+/// Precision and recall of the matches, sorted by distance, at evenly spaced
+/// distance thresholds. Recall is relative to the number of correspondences.
+fn pr_curve(matches: &[(f32, bool)], correspondences: usize, steps: usize) -> Vec<PrPoint> {
+    let (Some(first), Some(last)) = (matches.first(), matches.last()) else {
+        return vec![];
+    };
+
+    let mut curve = Vec::with_capacity(steps);
+    let (mut accepted, mut correct) = (0, 0);
+    for step in 1..=steps {
+        let threshold = first.0 + (last.0 - first.0) * step as f32 / steps as f32;
+        while accepted < matches.len() && matches[accepted].0 <= threshold {
+            correct += matches[accepted].1 as usize;
+            accepted += 1;
+        }
+        curve.push(PrPoint {
+            threshold,
+            precision: if accepted > 0 { correct as f32 / accepted as f32 } else { 1.0 },
+            recall: correct as f32 / correspondences.max(1) as f32,
+        });
+    }
+    curve
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{
+        evaluate, evaluate_features, identity, invert, multiply, project, read_homography, rotation_about,
+        scaling_about, synthetic_homographies, warp_perspective, EvaluationParams,
+    };
+    use crate::image::GrayFloatImage;
+    use crate::orb::{OrbExtractor, OrbSettings};
+    use crate::KeyPoint;
+
+    #[test]
+    fn homography_algebra() {
+        let h = multiply(&rotation_about(30.0, 50.0, 40.0), &scaling_about(0.7, 10.0, 20.0));
+        let inverse = invert(&h).unwrap();
+        let (x, y) = project(&h, 12.0, 34.0).unwrap();
+        let (bx, by) = project(&inverse, x, y).unwrap();
+        assert!((bx - 12.0).abs() < 1e-3 && (by - 34.0).abs() < 1e-3);
+
+        let (rx, ry) = project(&rotation_about(90.0, 0.0, 0.0), 1.0, 0.0).unwrap();
+        assert!(rx.abs() < 1e-6 && (ry - 1.0).abs() < 1e-6);
+        assert!(invert(&[[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 0.0, 1.0]]).is_none());
+    }
+
+    #[test]
+    fn invertibility_does_not_depend_on_the_scale() {
+        let h = multiply(&rotation_about(30.0, 50.0, 40.0), &scaling_about(0.7, 10.0, 20.0));
+        for scale in [1e-3, 1.0, 1e3] {
+            let scaled = h.map(|row| row.map(|v| v * scale));
+            let inverse = invert(&scaled).unwrap();
+            let (x, y) = project(&scaled, 12.0, 34.0).unwrap();
+            let (bx, by) = project(&inverse, x, y).unwrap();
+            assert!((bx - 12.0).abs() < 1e-3 && (by - 34.0).abs() < 1e-3);
+        }
+        assert!(invert(&[[1.0, 0.0, 500.0], [0.0, 1.0, 400.0], [0.0, 0.0, 1.0]]).is_some());
+        // Rows 1 and 2 are parallel up to rounding.
+        assert!(invert(&[[1e3, 2e3, 0.0], [500.0, 1000.0001, 0.0], [0.0, 0.0, 1e3]]).is_none());
+        assert!(invert(&[[0.0; 3]; 3]).is_none());
+    }
+
+    #[test]
+    fn singular_homography_files_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("H1to2");
+        std::fs::write(&path, "1 0 5\n0 1 -3\n0 0 1\n").unwrap();
+        assert_eq!(read_homography(&path).unwrap()[0][2], 5.0);
+
+        std::fs::write(&path, "1 2 3\n2 4 6\n0 0 1\n").unwrap();
+        assert_eq!(read_homography(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn exact_features_are_fully_repeatable() {
+        let image = GrayFloatImage::new(100, 100);
+        let h = scaling_about(0.5, 0.0, 0.0);
+        let keypoints1: Vec<KeyPoint> = (0..10).map(|i| KeyPoint::new(10.0 * i as f32 + 5.0, 30.0, 31.0)).collect();
+        let keypoints2: Vec<KeyPoint> = keypoints1.iter().map(|k| KeyPoint::new(k.x / 2.0 + 0.5, 15.0, 31.0)).collect();
+        let descriptors: Vec<[u8; 32]> = (0..10).map(|i| [i as u8; 32]).collect();
+
+        let evaluation = evaluate_features(
+            (&keypoints1, &descriptors, &image),
+            (&keypoints2, &descriptors, &image),
+            &h,
+            &EvaluationParams::default(),
+        );
+        assert_eq!(evaluation.correspondences, 10);
+        assert_eq!(evaluation.repeatability, 1.0);
+        assert!((evaluation.localization_error - 0.5).abs() < 1e-5);
+        assert_eq!(evaluation.matching_score, 1.0);
+        assert_eq!(evaluation.pr_curve.last().unwrap().recall, 1.0);
+    }
+
+    #[test]
+    fn orb_on_synthetic_warps() {
+        let image = GrayFloatImage::load_image("input-image/test1.png");
+        let (width, height) = (image.width() as u32, image.height() as u32);
+        let orb = OrbExtractor::new(OrbSettings { n_features: 500, ..OrbSettings::default() });
+        let params = EvaluationParams::default();
+
+        let same = evaluate(&orb, &orb, &image, &image, &identity(), &params);
+        assert!(same.repeatability > 0.99 && same.matching_score > 0.95);
+
+        let (label, h) = &synthetic_homographies(width, height)[1];
+        let warped = warp_perspective(&image, h, width, height);
+        let evaluation = evaluate(&orb, &orb, &image, &warped, h, &params);
+        assert!(evaluation.repeatability > 0.5, "{}: {:?}", label, evaluation);
+        assert!(evaluation.localization_error < 1.5, "{}: {:?}", label, evaluation);
+        // The checkerboard is repetitive, most nearest neighbours are a look-alike
+        // corner elsewhere on the board.
+        assert!(evaluation.matching_score > 0.05, "{}: {:?}", label, evaluation);
+    }
+}
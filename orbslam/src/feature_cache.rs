@@ -0,0 +1,370 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::io::{invalid_data, read_array, read_f32, read_u32};
+use crate::KeyPoint;
+
+const MAGIC: &[u8; 4] = b"FEAT";
+const VERSION: u16 = 1;
+
+/// Descriptors with a fixed size byte representation.
+pub trait DescriptorBytes: Sized {
+    const SIZE: usize;
+
+    fn write_bytes(&self, out: &mut Vec<u8>);
+
+    /// Reads a descriptor from exactly `SIZE` bytes.
+    fn from_bytes(bytes: &[u8]) -> Self;
+}
+
+impl<const N: usize> DescriptorBytes for [u8; N] {
+    const SIZE: usize = N;
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        bytes.try_into().unwrap()
+    }
+}
+
+impl<const N: usize> DescriptorBytes for [f32; N] {
+    const SIZE: usize = 4 * N;
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        for value in self {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        std::array::from_fn(|i| f32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap()))
+    }
+}
+
+/// Identifies a frame of a sequence: the image it was read from and its timestamp.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameKey {
+    pub image: String,
+    pub timestamp: f64,
+}
+
+impl FrameKey {
+    pub fn new(image: &str, timestamp: f64) -> FrameKey {
+        FrameKey { image: image.to_string(), timestamp }
+    }
+
+    /// Stable file name for the key, a 64-bit FNV-1a hash of the image path and
+    /// timestamp.
+    fn file_name(&self) -> String {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in self.image.bytes().chain(self.timestamp.to_le_bytes()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        format!("{:016x}.feat", hash)
+    }
+}
+
+/// Keypoints and aligned descriptors extracted from one frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameFeatures<D> {
+    pub key: FrameKey,
+    pub keypoints: Vec<KeyPoint>,
+    pub descriptors: Vec<D>,
+}
+
+impl<D: DescriptorBytes> FrameFeatures<D> {
+    pub fn new(key: FrameKey, keypoints: Vec<KeyPoint>, descriptors: Vec<D>) -> Self {
+        assert_eq!(keypoints.len(), descriptors.len(), "descriptors must be aligned with keypoints");
+        FrameFeatures { key, keypoints, descriptors }
+    }
+
+    /// Writes the features in a little-endian binary format: a header with the
+    /// frame key, descriptor size and feature count, then 28 bytes per keypoint
+    /// and the raw descriptors.
+    pub fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut out = Vec::with_capacity(32 + self.key.image.len() + self.keypoints.len() * (28 + D::SIZE));
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.key.image.len() as u32).to_le_bytes());
+        out.extend_from_slice(self.key.image.as_bytes());
+        out.extend_from_slice(&self.key.timestamp.to_le_bytes());
+        out.extend_from_slice(&(D::SIZE as u32).to_le_bytes());
+        out.extend_from_slice(&(self.keypoints.len() as u32).to_le_bytes());
+
+        for keypoint in self.keypoints.iter() {
+            for value in [keypoint.x, keypoint.y, keypoint.size, keypoint.angle, keypoint.response] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            out.extend_from_slice(&(keypoint.octave as u32).to_le_bytes());
+            out.extend_from_slice(&keypoint.class_id.to_le_bytes());
+        }
+        for descriptor in self.descriptors.iter() {
+            descriptor.write_bytes(&mut out);
+        }
+
+        writer.write_all(&out)
+    }
+
+    pub fn read_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
+        if &read_array::<_, 4>(reader)? != MAGIC {
+            return Err(invalid_data("not a feature file"));
+        }
+        if u16::from_le_bytes(read_array(reader)?) != VERSION {
+            return Err(invalid_data("unsupported feature file version"));
+        }
+
+        // Lengths and counts come from a file that may be stale or corrupt, so
+        // nothing is allocated from them up front.
+        let image_len = read_u32(reader)? as usize;
+        let image = String::from_utf8(read_bytes(reader, image_len)?).map_err(|_| invalid_data("image path is not UTF-8"))?;
+        let timestamp = f64::from_le_bytes(read_array(reader)?);
+
+        if read_u32(reader)? as usize != D::SIZE {
+            return Err(invalid_data("descriptor size does not match"));
+        }
+        let count = read_u32(reader)? as usize;
+
+        let mut keypoints = vec![];
+        for _ in 0..count {
+            keypoints.push(KeyPoint {
+                x: read_f32(reader)?,
+                y: read_f32(reader)?,
+                size: read_f32(reader)?,
+                angle: read_f32(reader)?,
+                response: read_f32(reader)?,
+                octave: read_u32(reader)? as usize,
+                class_id: i32::from_le_bytes(read_array(reader)?),
+            });
+        }
+
+        let len = count.checked_mul(D::SIZE).ok_or_else(|| invalid_data("too many descriptors"))?;
+        let bytes = read_bytes(reader, len)?;
+        let descriptors = bytes.chunks_exact(D::SIZE).map(D::from_bytes).collect();
+
+        Ok(FrameFeatures { key: FrameKey { image, timestamp }, keypoints, descriptors })
+    }
+
+    pub fn save_binary<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_binary(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load_binary<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        FrameFeatures::read_binary(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonFrame {
+    image: String,
+    timestamp: f64,
+    keypoints: Vec<KeyPoint>,
+    /// Descriptors as hex strings.
+    descriptors: Vec<String>,
+}
+
+#[cfg(feature = "serde")]
+impl<D: DescriptorBytes> FrameFeatures<D> {
+    /// Human readable form of the features, for debugging.
+    pub fn to_json(&self) -> String {
+        let descriptors = self
+            .descriptors
+            .iter()
+            .map(|descriptor| {
+                let mut bytes = Vec::with_capacity(D::SIZE);
+                descriptor.write_bytes(&mut bytes);
+                bytes.iter().map(|b| format!("{:02x}", b)).collect()
+            })
+            .collect();
+        let frame = JsonFrame {
+            image: self.key.image.clone(),
+            timestamp: self.key.timestamp,
+            keypoints: self.keypoints.clone(),
+            descriptors,
+        };
+        serde_json::to_string_pretty(&frame).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let frame: JsonFrame = serde_json::from_str(json).map_err(|e| invalid_data(&e.to_string()))?;
+        let descriptors = frame
+            .descriptors
+            .iter()
+            .map(|hex| {
+                if hex.len() != 2 * D::SIZE || !hex.is_ascii() {
+                    return Err(invalid_data("descriptor size does not match"));
+                }
+                let bytes = (0..D::SIZE)
+                    .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| invalid_data("descriptor is not hex"))?;
+                Ok(D::from_bytes(&bytes))
+            })
+            .collect::<io::Result<Vec<D>>>()?;
+        if descriptors.len() != frame.keypoints.len() {
+            return Err(invalid_data("descriptors must be aligned with keypoints"));
+        }
+
+        Ok(FrameFeatures {
+            key: FrameKey { image: frame.image, timestamp: frame.timestamp },
+            keypoints: frame.keypoints,
+            descriptors,
+        })
+    }
+}
+
+/// A directory of per-frame feature files, so that a sequence only goes through
+/// feature extraction once. The cache does not know which extractor produced the
+/// features: use one directory per extractor configuration.
+pub struct FeatureCache {
+    dir: PathBuf,
+}
+
+impl FeatureCache {
+    /// Opens the cache in `dir`, creating the directory if needed.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<FeatureCache> {
+        fs::create_dir_all(&dir)?;
+        Ok(FeatureCache { dir: dir.as_ref().to_path_buf() })
+    }
+
+    pub fn path(&self, key: &FrameKey) -> PathBuf {
+        self.dir.join(key.file_name())
+    }
+
+    /// Cached features of a frame, `None` when the frame is not cached.
+    pub fn load<D: DescriptorBytes>(&self, key: &FrameKey) -> io::Result<Option<FrameFeatures<D>>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let features = FrameFeatures::load_binary(path)?;
+        Ok((features.key == *key).then_some(features))
+    }
+
+    pub fn store<D: DescriptorBytes>(&self, features: &FrameFeatures<D>) -> io::Result<()> {
+        // Write then rename so an interrupted run never leaves a truncated file.
+        let path = self.path(&features.key);
+        let partial = path.with_extension("partial");
+        features.save_binary(&partial)?;
+        fs::rename(partial, path)
+    }
+
+    /// Cached features of a frame, or the result of `extract` which is then cached.
+    pub fn get_or_extract<D, F>(&self, key: &FrameKey, extract: F) -> io::Result<FrameFeatures<D>>
+    where
+        D: DescriptorBytes,
+        F: FnOnce() -> (Vec<KeyPoint>, Vec<D>),
+    {
+        if let Some(features) = self.load(key)? {
+            return Ok(features);
+        }
+        let (keypoints, descriptors) = extract();
+        let features = FrameFeatures::new(key.clone(), keypoints, descriptors);
+        self.store(&features)?;
+        Ok(features)
+    }
+}
+
+/// Reads exactly `len` bytes, growing the buffer with the data actually read.
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated feature file"));
+    }
+    Ok(bytes)
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::io::Cursor;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{FeatureCache, FrameFeatures, FrameKey};
+    use crate::KeyPoint;
+
+    fn random_features(seed: u64, image: &str) -> FrameFeatures<[u8; 32]> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let keypoints = (0..50)
+            .map(|i| KeyPoint {
+                angle: rng.gen_range(0.0..360.0),
+                response: rng.gen(),
+                octave: i % 8,
+                class_id: i as i32 - 10,
+                ..KeyPoint::new(rng.gen_range(0.0..640.0), rng.gen_range(0.0..480.0), 31.0)
+            })
+            .collect();
+        let descriptors = (0..50).map(|_| rng.gen()).collect();
+        FrameFeatures::new(FrameKey::new(image, 1305031102.175304), keypoints, descriptors)
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let features = random_features(1, "rgb/1305031102.175304.png");
+        let mut bytes = vec![];
+        features.write_binary(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4 + 2 + 4 + 25 + 8 + 4 + 4 + 50 * (28 + 32));
+
+        assert_eq!(FrameFeatures::read_binary(&mut Cursor::new(&bytes)).unwrap(), features);
+        assert!(FrameFeatures::<[u8; 64]>::read_binary(&mut Cursor::new(&bytes)).is_err());
+        assert!(FrameFeatures::<[u8; 32]>::read_binary(&mut Cursor::new(&bytes[..100])).is_err());
+    }
+
+    #[test]
+    fn corrupted_headers_are_errors() {
+        let features = random_features(6, "rgb/0003.png");
+        let mut bytes = vec![];
+        features.write_binary(&mut bytes).unwrap();
+        let count_offset = 4 + 2 + 4 + 12 + 8 + 4;
+
+        // Huge image path length, then huge feature count.
+        let mut corrupted = bytes.clone();
+        corrupted[6..10].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(FrameFeatures::<[u8; 32]>::read_binary(&mut Cursor::new(&corrupted)).is_err());
+
+        let mut corrupted = bytes.clone();
+        corrupted[count_offset..count_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = FrameFeatures::<[u8; 32]>::read_binary(&mut Cursor::new(&corrupted)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn cache_extracts_each_frame_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FeatureCache::open(dir.path().join("orb")).unwrap();
+        let calls = Cell::new(0);
+        let extract = |seed| {
+            calls.set(calls.get() + 1);
+            let features = random_features(seed, "unused");
+            (features.keypoints, features.descriptors)
+        };
+
+        let key = FrameKey::new("rgb/0001.png", 0.1);
+        let first = cache.get_or_extract(&key, || extract(2)).unwrap();
+        let second = cache.get_or_extract(&key, || extract(3)).unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(first, second);
+
+        let other = FrameKey::new("rgb/0001.png", 0.2);
+        assert!(cache.load::<[u8; 32]>(&other).unwrap().is_none());
+        cache.get_or_extract(&other, || extract(4)).unwrap();
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let features = random_features(5, "rgb/0002.png");
+        let json = features.to_json();
+        assert_eq!(FrameFeatures::from_json(&json).unwrap(), features);
+    }
+}
@@ -0,0 +1,165 @@
+use wide::u64x4;
+
+const M1: u64 = 0x5555_5555_5555_5555;
+const M2: u64 = 0x3333_3333_3333_3333;
+const M4: u64 = 0x0f0f_0f0f_0f0f_0f0f;
+const M8: u64 = 0x00ff_00ff_00ff_00ff;
+
+/// Number of differing bits between two binary descriptors of the same length.
+pub fn hamming_distance(a: &[u8], b: &[u8]) -> u32 {
+    debug_assert_eq!(a.len(), b.len());
+    let mut distance = 0;
+    let mut chunks_a = a.chunks_exact(8);
+    let mut chunks_b = b.chunks_exact(8);
+    for (ca, cb) in chunks_a.by_ref().zip(chunks_b.by_ref()) {
+        let wa = u64::from_le_bytes(ca.try_into().unwrap());
+        let wb = u64::from_le_bytes(cb.try_into().unwrap());
+        distance += (wa ^ wb).count_ones();
+    }
+    for (ba, bb) in chunks_a.remainder().iter().zip(chunks_b.remainder()) {
+        distance += (ba ^ bb).count_ones();
+    }
+    distance
+}
+
+#[inline]
+fn word(descriptor: &[u8; 32], i: usize) -> u64 {
+    u64::from_le_bytes(descriptor[i * 8..i * 8 + 8].try_into().unwrap())
+}
+
+#[inline]
+fn load(descriptor: &[u8; 32]) -> u64x4 {
+    u64x4::new([word(descriptor, 0), word(descriptor, 1), word(descriptor, 2), word(descriptor, 3)])
+}
+
+/// Per-byte bit counts of every lane: each byte of the result holds the number of
+/// set bits of the same byte of `x`, in `0..=8`.
+#[inline]
+fn byte_popcount(x: u64x4) -> u64x4 {
+    let x = x - ((x >> 1) & u64x4::splat(M1));
+    let x = (x & u64x4::splat(M2)) + ((x >> 2) & u64x4::splat(M2));
+    (x + (x >> 4)) & u64x4::splat(M4)
+}
+
+/// Sums the per-byte counts of every lane into its low 16 bits.
+#[inline]
+fn fold_bytes(x: u64x4) -> u64x4 {
+    let x = (x & u64x4::splat(M8)) + ((x >> 8) & u64x4::splat(M8));
+    let x = x + (x >> 16);
+    let x = x + (x >> 32);
+    x & u64x4::splat(0xffff)
+}
+
+/// Hamming distance between two 256-bit descriptors, with the four 64-bit words
+/// counted in parallel lanes.
+pub fn hamming_distance_256(a: &[u8; 32], b: &[u8; 32]) -> u32 {
+    fold_bytes(byte_popcount(load(a) ^ load(b))).to_array().iter().sum::<u64>() as u32
+}
+
+/// Hamming distances from `query` to every descriptor of `train`, written to
+/// `distances` in the same order. Train descriptors are processed four at a time,
+/// one per lane, so no horizontal reduction is needed per descriptor.
+pub fn hamming_one_to_many(query: &[u8; 32], train: &[[u8; 32]], distances: &mut Vec<u32>) {
+    distances.clear();
+    distances.reserve(train.len());
+
+    let query_words = [0, 1, 2, 3].map(|i| u64x4::splat(word(query, i)));
+
+    let mut blocks = train.chunks_exact(4);
+    for block in blocks.by_ref() {
+        // Byte counts of the four words add up to at most 32 per byte.
+        let mut counts = u64x4::splat(0);
+        for (i, query_word) in query_words.iter().enumerate() {
+            let words = u64x4::new([word(&block[0], i), word(&block[1], i), word(&block[2], i), word(&block[3], i)]);
+            counts += byte_popcount(*query_word ^ words);
+        }
+        distances.extend(fold_bytes(counts).to_array().iter().map(|d| *d as u32));
+    }
+
+    for descriptor in blocks.remainder() {
+        distances.push(hamming_distance_256(query, descriptor));
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{hamming_distance, hamming_distance_256, hamming_one_to_many};
+
+    fn random_descriptors(n: usize) -> Vec<[u8; 32]> {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..n).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn hamming_distance_counts_bits() {
+        let a = [0u8; 32];
+        let mut b = [0u8; 32];
+        b[0] = 0b1011;
+        b[31] = 0xff;
+        assert_eq!(hamming_distance(&a, &b), 11);
+        assert_eq!(hamming_distance(&a[..3], &b[..3]), 3);
+        assert_eq!(hamming_distance_256(&a, &b), 11);
+        assert_eq!(hamming_distance_256(&a, &[0xff; 32]), 256);
+    }
+
+    #[test]
+    fn simd_kernels_match_scalar() {
+        let descriptors = random_descriptors(103);
+        let mut distances = vec![];
+
+        for query in descriptors.iter().take(10) {
+            hamming_one_to_many(query, &descriptors, &mut distances);
+            assert_eq!(distances.len(), descriptors.len());
+            for (train, distance) in descriptors.iter().zip(distances.iter()) {
+                assert_eq!(*distance, hamming_distance(query, train));
+                assert_eq!(hamming_distance_256(query, train), hamming_distance(query, train));
+            }
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_hamming_scalar_vs_simd() {
+        let query = random_descriptors(2000);
+        let train = random_descriptors(2000);
+
+        let start = Instant::now();
+        let mut scalar_sum = 0u64;
+        for q in query.iter() {
+            for t in train.iter() {
+                scalar_sum += hamming_distance(q, t) as u64;
+            }
+        }
+        let scalar_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut pairwise_sum = 0u64;
+        for q in query.iter() {
+            for t in train.iter() {
+                pairwise_sum += hamming_distance_256(q, t) as u64;
+            }
+        }
+        let pairwise_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut batched_sum = 0u64;
+        let mut distances = vec![];
+        for q in query.iter() {
+            hamming_one_to_many(q, &train, &mut distances);
+            batched_sum += distances.iter().map(|d| *d as u64).sum::<u64>();
+        }
+        let batched_time = start.elapsed();
+
+        println!("scalar      : {:?}", scalar_time);
+        println!("simd pair   : {:?}", pairwise_time);
+        println!("simd batch  : {:?}", batched_time);
+        assert_eq!(scalar_sum, pairwise_sum);
+        assert_eq!(scalar_sum, batched_sum);
+    }
+}
@@ -11,7 +11,16 @@ impl Harris {
     pub fn corner_detector(image: &GrayFloatImage, window_size: usize, k: f32, threshold: f32) -> Vec<(usize, usize)> {
 
         let start = Instant::now();
-        
+
+        let r = Harris::corner_response(image, window_size, k);
+
+        let supression = non_maximum_suppression(&r, image.width(), image.height(), threshold);
+        info!("Corner detector response in : {:?}", start.elapsed());
+        supression
+    }
+
+    /// Harris response `det(M) - k * trace(M)^2` of every pixel, indexed `[[y, x]]`.
+    pub fn corner_response(image: &GrayFloatImage, window_size: usize, k: f32) -> Array2<f32> {
         let gaussian_image = gaussian_blur(image, 2.0);
 
         let i_x = sobel_filter_x(&image);
@@ -43,10 +52,8 @@ impl Harris {
              }
         }
 
-        let supression = non_maximum_suppression(&r, image.width(), image.height(), threshold);
-        info!("Corner detector response in : {:?}", start.elapsed());
-        supression
-    }    
+        r
+    }
 }
 
 fn integral(img: &Array2<f32>) -> Array2<f32> {
@@ -81,7 +88,7 @@ fn sum_rect(integral: &Array2<f32>, x: usize, y: usize, window_size: usize) -> f
     sum
 }
 
-fn non_maximum_suppression(
+pub(crate) fn non_maximum_suppression(
     r: &Array2<f32>,
     width: usize,
     height: usize,
@@ -1,5 +1,5 @@
 use derive_more::{Deref, DerefMut};
-use image::{DynamicImage, ImageBuffer, Luma, Pixel};
+use image::{imageops::FilterType, DynamicImage, ImageBuffer, Luma, Pixel};
 use log::*;
 use ndarray::{Array, Array2, ArrayView2};
 use nshare::RefNdarray2;
@@ -136,6 +136,21 @@ impl GrayFloatImage {
         self.get_pixel(x as u32, y as u32)[0]
     }
 
+    /// Bilinear interpolation at sub-pixel position `(x, y)`, with the border
+    /// replicated outside the image.
+    pub fn sample_bilinear(&self, x: f32, y: f32) -> f32 {
+        let (max_x, max_y) = ((self.width() - 1) as f32, (self.height() - 1) as f32);
+        let (x, y) = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as usize, y0 as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width() - 1), (y0 + 1).min(self.height() - 1));
+
+        let top = self.get(x0, y0) * (1.0 - fx) + self.get(x1, y0) * fx;
+        let bottom = self.get(x0, y1) * (1.0 - fx) + self.get(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
     pub fn put(&mut self, x: usize, y: usize, pixel_value: f32) {
         self.put_pixel(x as u32, y as u32, Luma([pixel_value]));
     }
@@ -197,21 +212,81 @@ pub fn gaussian_kernel(r: f32, kernel_size: usize) -> Vec<f32> {
 pub fn gaussian_blur(image: &GrayFloatImage, r: f32) -> GrayFloatImage  {
     let kernel_radius = (2.0 * r).ceil() as usize;
     let kernel_size = kernel_radius * 2 + 1;
+    gaussian_blur_with_size(image, r, kernel_size)
+}
+
+/// Separable Gaussian blur with an explicit (odd) kernel size. Borders are mirrored
+/// without repeating the edge pixel, like OpenCV's `BORDER_REFLECT_101`.
+pub fn gaussian_blur_with_size(image: &GrayFloatImage, r: f32, kernel_size: usize) -> GrayFloatImage {
     let kernel = gaussian_kernel(r, kernel_size);
-    
-    let blurred_array = convolve(image, &kernel, kernel_size);
-    
-    let mut blurred_image = GrayFloatImage::new(image.width() as u32, image.height() as u32);
-    for y in 0..image.height() {
-        for x in 0..image.width() {
-            let pixel_value = blurred_array[[y as usize, x as usize]];
-            blurred_image.put_pixel(x as u32, y as u32, Luma([pixel_value]));
+    let half_k = (kernel_size / 2) as isize;
+    let (width, height) = (image.width(), image.height());
+    let src = image.as_raw();
+
+    let mut horizontal = vec![0f32; width * height];
+    for y in 0..height {
+        let row = &src[y * width..(y + 1) * width];
+        for x in 0..width {
+            let mut sum = 0.0;
+            for (k, weight) in kernel.iter().enumerate() {
+                let px = reflect_101(x as isize + k as isize - half_k, width);
+                sum += row[px] * weight;
+            }
+            horizontal[y * width + x] = sum;
+        }
+    }
+
+    let mut blurred_image = GrayFloatImage::new(width as u32, height as u32);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            for (k, weight) in kernel.iter().enumerate() {
+                let py = reflect_101(y as isize + k as isize - half_k, height);
+                sum += horizontal[py * width + x] * weight;
+            }
+            blurred_image.put(x, y, sum);
         }
     }
 
     blurred_image
 }
 
+fn reflect_101(i: isize, len: usize) -> usize {
+    let len = len as isize;
+    if len == 1 {
+        return 0;
+    }
+    let mut i = i;
+    while i < 0 || i >= len {
+        i = if i < 0 { -i } else { 2 * len - 2 - i };
+    }
+    i as usize
+}
+
+/// Bilinear resize to an exact size.
+pub fn resize(image: &GrayFloatImage, width: u32, height: u32) -> GrayFloatImage {
+    GrayFloatImage(image::imageops::resize(&image.0, width, height, FilterType::Triangle))
+}
+
+/// Image pyramid with `n_levels` levels, each `scale_factor` times smaller than the
+/// previous one and resampled from it. Level 0 is a copy of `image`.
+pub fn build_pyramid(image: &GrayFloatImage, n_levels: usize, scale_factor: f32) -> Vec<GrayFloatImage> {
+    let mut pyramid: Vec<GrayFloatImage> = Vec::with_capacity(n_levels);
+    let mut scale = 1.0;
+    for level in 0..n_levels {
+        if level == 0 {
+            pyramid.push(image.clone());
+            continue;
+        }
+        scale /= scale_factor;
+        let width = (image.width() as f32 * scale).round().max(1.0) as u32;
+        let height = (image.height() as f32 * scale).round().max(1.0) as u32;
+        let level_image = resize(&pyramid[level - 1], width, height);
+        pyramid.push(level_image);
+    }
+    pyramid
+}
+
 
 fn convolve<T: Kernel>(image: &GrayFloatImage, kernel: &T, kernel_size: usize) -> Array2<f32> {
     let (width, height) = (image.width() as usize, image.height() as usize);
@@ -234,6 +309,22 @@ fn convolve<T: Kernel>(image: &GrayFloatImage, kernel: &T, kernel_size: usize) -
     result
 }
 
+/// Square image of independent uniform noise in `[0, 1)`, the random texture
+/// shared by the detector and descriptor tests.
+#[cfg(test)]
+pub(crate) fn noise_image(size: u32, seed: u64) -> GrayFloatImage {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut image = GrayFloatImage::new(size, size);
+    for y in 0..size as usize {
+        for x in 0..size as usize {
+            image.put(x, y, rng.gen());
+        }
+    }
+    image
+}
+
 
 #[cfg(test)]
 mod test {
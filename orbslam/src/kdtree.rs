@@ -0,0 +1,254 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::matcher::{l2_distance, Match};
+
+/// Parameters of a [`KdTree`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KdTreeParams {
+    /// Nodes with at most this many points are not split further.
+    pub leaf_size: usize,
+    /// Maximum number of points compared per query, exact search when `None`.
+    pub max_checks: Option<usize>,
+}
+
+impl Default for KdTreeParams {
+    fn default() -> Self {
+        KdTreeParams {
+            leaf_size: 8,
+            max_checks: None,
+        }
+    }
+}
+
+enum Node {
+    /// Range `start..end` of `KdTree::order`.
+    Leaf { start: usize, end: usize },
+    Split { dimension: usize, value: f32, left: usize, right: usize },
+}
+
+/// A branch to visit later, ordered by its lower bound on the squared distance to
+/// the query, smallest first.
+struct Branch {
+    bound: f32,
+    node: usize,
+}
+
+impl PartialEq for Branch {
+    fn eq(&self, other: &Self) -> bool {
+        self.bound == other.bound
+    }
+}
+
+impl Eq for Branch {}
+
+impl PartialOrd for Branch {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Branch {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.bound.total_cmp(&self.bound)
+    }
+}
+
+/// Nearest-neighbour index for float descriptors of `N` dimensions under the L2
+/// distance. Nodes are split at the median of the dimension of largest variance
+/// and queries visit the leaves best bin first (Beis and Lowe, "Shape indexing
+/// using approximate nearest-neighbour search in high-dimensional spaces"),
+/// which is exact unless `max_checks` stops the search early.
+///
+/// Descriptor ids are their positions in the slice given to [`KdTree::build`].
+pub struct KdTree<const N: usize> {
+    params: KdTreeParams,
+    descriptors: Vec<[f32; N]>,
+    order: Vec<usize>,
+    nodes: Vec<Node>,
+}
+
+impl<const N: usize> KdTree<N> {
+    pub fn build(descriptors: &[[f32; N]], params: KdTreeParams) -> Self {
+        let mut tree = KdTree {
+            params,
+            descriptors: descriptors.to_vec(),
+            order: (0..descriptors.len()).collect(),
+            nodes: vec![],
+        };
+        tree.build_node(0, descriptors.len());
+        tree
+    }
+
+    pub fn params(&self) -> &KdTreeParams {
+        &self.params
+    }
+
+    pub fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descriptors.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&[f32; N]> {
+        self.descriptors.get(id)
+    }
+
+    /// Builds the node over `order[start..end]` and returns its index.
+    fn build_node(&mut self, start: usize, end: usize) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(Node::Leaf { start, end });
+        if end - start <= self.params.leaf_size.max(1) {
+            return index;
+        }
+
+        let dimension = self.widest_dimension(start, end);
+        let middle = start + (end - start) / 2;
+        let descriptors = &self.descriptors;
+        self.order[start..end].select_nth_unstable_by(middle - start, |a, b| {
+            descriptors[*a][dimension].total_cmp(&descriptors[*b][dimension])
+        });
+        let value = self.descriptors[self.order[middle]][dimension];
+
+        let left = self.build_node(start, middle);
+        let right = self.build_node(middle, end);
+        self.nodes[index] = Node::Split { dimension, value, left, right };
+        index
+    }
+
+    fn widest_dimension(&self, start: usize, end: usize) -> usize {
+        let count = (end - start) as f32;
+        let mut mean = [0f32; N];
+        for &id in &self.order[start..end] {
+            for (m, v) in mean.iter_mut().zip(self.descriptors[id].iter()) {
+                *m += v / count;
+            }
+        }
+        let mut variance = [0f32; N];
+        for &id in &self.order[start..end] {
+            for ((s, m), v) in variance.iter_mut().zip(mean.iter()).zip(self.descriptors[id].iter()) {
+                *s += (v - m) * (v - m);
+            }
+        }
+        (0..N).max_by(|a, b| variance[*a].total_cmp(&variance[*b])).unwrap_or(0)
+    }
+
+    /// Up to `k` descriptors closest to `descriptor` as `(id, distance)`, closest
+    /// first.
+    pub fn query(&self, descriptor: &[f32; N], k: usize) -> Vec<(usize, f32)> {
+        let mut nearest: Vec<(usize, f32)> = Vec::with_capacity(k + 1);
+        if k == 0 || self.is_empty() {
+            return nearest;
+        }
+
+        let max_checks = self.params.max_checks.unwrap_or(usize::MAX);
+        let mut checks = 0;
+        let mut branches = BinaryHeap::new();
+        branches.push(Branch { bound: 0.0, node: 0 });
+
+        while let Some(Branch { bound, node }) = branches.pop() {
+            if checks >= max_checks {
+                break;
+            }
+            if nearest.len() == k && bound >= nearest[k - 1].1 * nearest[k - 1].1 {
+                break;
+            }
+
+            // Descend to a leaf, queueing the far sides of the splits on the way.
+            let mut node = node;
+            loop {
+                match self.nodes[node] {
+                    Node::Split { dimension, value, left, right } => {
+                        let offset = descriptor[dimension] - value;
+                        let (near, far) = if offset < 0.0 { (left, right) } else { (right, left) };
+                        branches.push(Branch { bound: bound.max(offset * offset), node: far });
+                        node = near;
+                    }
+                    Node::Leaf { start, end } => {
+                        for &id in &self.order[start..end] {
+                            let distance = l2_distance(descriptor, &self.descriptors[id]);
+                            checks += 1;
+                            if nearest.len() == k && distance >= nearest[k - 1].1 {
+                                continue;
+                            }
+                            let position = nearest.partition_point(|&(_, d)| d <= distance);
+                            nearest.insert(position, (id, distance));
+                            nearest.truncate(k);
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+
+    /// Counterpart of [`crate::matcher::knn_match`] with the tree ids as
+    /// `train_idx`.
+    pub fn knn_match(&self, queries: &[[f32; N]], k: usize) -> Vec<Vec<Match>> {
+        queries
+            .iter()
+            .enumerate()
+            .map(|(query_idx, query)| {
+                self.query(query, k)
+                    .into_iter()
+                    .map(|(id, distance)| Match::new(query_idx, id, distance))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{KdTree, KdTreeParams};
+    use crate::matcher::knn_match;
+
+    fn clustered(rng: &mut StdRng, centers: &[[f32; 16]], n: usize) -> Vec<[f32; 16]> {
+        (0..n)
+            .map(|i| {
+                let center = &centers[i % centers.len()];
+                std::array::from_fn(|d| center[d] + rng.gen_range(-0.1..0.1))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exact_search_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let map: Vec<[f32; 16]> = (0..2000).map(|_| rng.gen()).collect();
+        let queries: Vec<[f32; 16]> = (0..100).map(|_| rng.gen()).collect();
+
+        let tree = KdTree::build(&map, KdTreeParams::default());
+        let exact = knn_match(&queries, &map, 3);
+        for (found, expected) in tree.knn_match(&queries, 3).iter().zip(exact.iter()) {
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn approximate_search_recall() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let centers: Vec<[f32; 16]> = (0..200).map(|_| rng.gen()).collect();
+        let map = clustered(&mut rng, &centers, 5000);
+        let queries = clustered(&mut rng, &centers, 300);
+
+        let params = KdTreeParams { max_checks: Some(256), ..KdTreeParams::default() };
+        let approximate = KdTree::build(&map, params).knn_match(&queries, 1);
+        let exact = knn_match(&queries, &map, 1);
+
+        let hits = approximate
+            .iter()
+            .zip(exact.iter())
+            .filter(|(a, e)| a.first().map(|m| m.train_idx) == Some(e[0].train_idx))
+            .count();
+        let recall = hits as f32 / queries.len() as f32;
+        assert!(recall > 0.9, "recall@1 = {}", recall);
+    }
+}
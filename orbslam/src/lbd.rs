@@ -0,0 +1,419 @@
+use crate::detectors::LineDescriptorExtractor;
+use crate::image::{sobel_filter_x, sobel_filter_y, GrayFloatImage};
+use crate::line::LineSegment;
+use crate::matcher::{Descriptor, Match};
+
+/// Number of bands of the line support region.
+pub const BANDS: usize = 9;
+/// Width of each band, in pixels.
+pub const BAND_WIDTH: usize = 7;
+/// Mean and standard deviation of 4 gradient sums per band.
+pub const LBD_SIZE: usize = 8 * BANDS;
+
+/// Line Band Descriptor, the means of all bands followed by their standard
+/// deviations, each half L2 normalised.
+pub type LbdDescriptor = [f32; LBD_SIZE];
+/// Binary LBD, 256 comparisons between the float descriptors of band pairs.
+pub type BinaryLbdDescriptor = [u8; 32];
+
+/// Entries are clamped to this value after a first normalisation, as in SIFT.
+const CLAMP: f32 = 0.4;
+
+/// Sobel gradients of an image, sampled with sub-pixel accuracy along lines.
+pub struct LineGradients {
+    gx: GrayFloatImage,
+    gy: GrayFloatImage,
+}
+
+impl LineGradients {
+    pub fn new(image: &GrayFloatImage) -> LineGradients {
+        LineGradients {
+            gx: GrayFloatImage::from_array2(sobel_filter_x(image)),
+            gy: GrayFloatImage::from_array2(sobel_filter_y(image)),
+        }
+    }
+
+    fn at(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let (max_x, max_y) = ((self.gx.width() - 1) as f32, (self.gx.height() - 1) as f32);
+        if !(0.0..=max_x).contains(&x) || !(0.0..=max_y).contains(&y) {
+            return None;
+        }
+        Some((self.gx.sample_bilinear(x, y), self.gy.sample_bilinear(x, y)))
+    }
+}
+
+/// LBD (Zhang and Koch, "An efficient and robust line segment matching approach
+/// based on LBD descriptor and pairwise geometric consistency").
+///
+/// The support region is made of `BANDS` bands of `BAND_WIDTH` rows parallel to
+/// the segment. Each row sums the positive and negative parts of the gradients
+/// projected on the normal and on the direction of the segment, weighted by a
+/// Gaussian across the whole region. Rows contribute to their own band and to the
+/// two neighbouring ones with a second Gaussian, and every band is described by
+/// the mean and standard deviation of its rows. The direction of the segment
+/// decides which side is which, so reversed segments get different descriptors.
+pub fn compute_lbd_descriptor(gradients: &LineGradients, line: &LineSegment) -> LbdDescriptor {
+    let rows = BANDS * BAND_WIDTH;
+    let (dx, dy) = line.direction();
+    // Normal to the segment, to the right of its direction in image coordinates.
+    let (nx, ny) = (-dy, dx);
+    let samples = line.length().round().max(1.0) as usize + 1;
+    let step = line.length() / (samples - 1) as f32;
+
+    let center = (rows - 1) as f32 / 2.0;
+    let global_sigma = 0.5 * (rows - 1) as f32;
+    let mut row_sums = vec![[0f32; 4]; rows];
+    for (row, sums) in row_sums.iter_mut().enumerate() {
+        let offset = row as f32 - center;
+        let weight = (-offset * offset / (2.0 * global_sigma * global_sigma)).exp();
+        for i in 0..samples {
+            let t = i as f32 * step;
+            let (x, y) = (line.x1 + t * dx + offset * nx, line.y1 + t * dy + offset * ny);
+            let Some((gx, gy)) = gradients.at(x, y) else {
+                continue;
+            };
+            let (across, along) = (gx * nx + gy * ny, gx * dx + gy * dy);
+            sums[if across > 0.0 { 0 } else { 1 }] += weight * across.abs();
+            sums[if along > 0.0 { 2 } else { 3 }] += weight * along.abs();
+        }
+    }
+
+    let local_sigma = BAND_WIDTH as f32;
+    let mut descriptor = [0f32; LBD_SIZE];
+    for band in 0..BANDS {
+        let band_center = (band * BAND_WIDTH) as f32 + (BAND_WIDTH - 1) as f32 / 2.0;
+        let first = band.saturating_sub(1) * BAND_WIDTH;
+        let last = ((band + 2) * BAND_WIDTH).min(rows);
+
+        let mut sum = [0f32; 4];
+        let mut sum_squares = [0f32; 4];
+        for (row, sums) in row_sums.iter().enumerate().take(last).skip(first) {
+            let distance = row as f32 - band_center;
+            let weight = (-distance * distance / (2.0 * local_sigma * local_sigma)).exp();
+            for k in 0..4 {
+                let value = weight * sums[k];
+                sum[k] += value;
+                sum_squares[k] += value * value;
+            }
+        }
+
+        let count = (last - first) as f32;
+        for k in 0..4 {
+            let mean = sum[k] / count;
+            descriptor[4 * band + k] = mean;
+            descriptor[4 * BANDS + 4 * band + k] = (sum_squares[k] / count - mean * mean).max(0.0).sqrt();
+        }
+    }
+
+    let (means, deviations) = descriptor.split_at_mut(4 * BANDS);
+    for half in [means, deviations] {
+        normalize(half);
+        for value in half.iter_mut() {
+            *value = value.min(CLAMP);
+        }
+        normalize(half);
+    }
+    descriptor
+}
+
+fn normalize(values: &mut [f32]) {
+    let norm = values.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > f32::EPSILON {
+        for value in values.iter_mut() {
+            *value /= norm;
+        }
+    }
+}
+
+/// Pairs of bands compared by [`binarize_lbd`], the closest ones first so that
+/// the 32 pairs fitting in 256 bits are the most stable ones.
+fn band_pairs() -> Vec<(usize, usize)> {
+    let mut pairs: Vec<(usize, usize)> =
+        (0..BANDS).flat_map(|i| (i + 1..BANDS).map(move |j| (i, j))).collect();
+    pairs.sort_by_key(|&(i, j)| (j - i, i));
+    pairs.truncate(32);
+    pairs
+}
+
+/// Binary LBD: for each band pair, the 4 means and the 4 standard deviations of
+/// the first band are compared with those of the second one, one bit each.
+pub fn binarize_lbd(descriptor: &LbdDescriptor) -> BinaryLbdDescriptor {
+    let mut binary = [0u8; 32];
+    for (byte, (i, j)) in binary.iter_mut().zip(band_pairs()) {
+        for k in 0..4 {
+            if descriptor[4 * i + k] > descriptor[4 * j + k] {
+                *byte |= 1 << k;
+            }
+            let (a, b) = (descriptor[4 * BANDS + 4 * i + k], descriptor[4 * BANDS + 4 * j + k]);
+            if a > b {
+                *byte |= 1 << (4 + k);
+            }
+        }
+    }
+    binary
+}
+
+/// Float LBD, matched by L2 distance.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LbdExtractor;
+
+impl LineDescriptorExtractor for LbdExtractor {
+    type Descriptor = LbdDescriptor;
+
+    fn compute_lines(&self, image: &GrayFloatImage, lines: &[LineSegment]) -> Vec<LbdDescriptor> {
+        let gradients = LineGradients::new(image);
+        lines.iter().map(|line| compute_lbd_descriptor(&gradients, line)).collect()
+    }
+}
+
+/// Binary LBD, matched by Hamming distance.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BinaryLbdExtractor;
+
+impl LineDescriptorExtractor for BinaryLbdExtractor {
+    type Descriptor = BinaryLbdDescriptor;
+
+    fn compute_lines(&self, image: &GrayFloatImage, lines: &[LineSegment]) -> Vec<BinaryLbdDescriptor> {
+        let gradients = LineGradients::new(image);
+        lines.iter().map(|line| binarize_lbd(&compute_lbd_descriptor(&gradients, line))).collect()
+    }
+}
+
+/// Line matcher combining descriptor distances with geometric consistency
+/// between the two frames. Candidates failing a geometric check are discarded
+/// before the best match and the ratio test are evaluated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LineMatcher {
+    /// Ratio for Lowe's test, disabled when `None`.
+    pub ratio: Option<f32>,
+    /// Matches with a larger distance are discarded.
+    pub max_distance: Option<f32>,
+    /// Keep only mutual best matches.
+    pub cross_check: bool,
+    /// Maximal angle between matched segments, in degrees.
+    pub max_angle: Option<f32>,
+    /// Minimal ratio of the shorter length to the longer one.
+    pub min_length_ratio: Option<f32>,
+    /// Minimal overlap of the segments along the longer one, as a fraction of
+    /// the shorter length.
+    pub min_overlap: Option<f32>,
+}
+
+impl Default for LineMatcher {
+    fn default() -> Self {
+        LineMatcher {
+            ratio: Some(0.8),
+            max_distance: None,
+            cross_check: true,
+            max_angle: Some(20.0),
+            min_length_ratio: Some(0.5),
+            min_overlap: None,
+        }
+    }
+}
+
+impl LineMatcher {
+    /// Whether `query` and `train` pass the geometric checks.
+    pub fn is_consistent(&self, query: &LineSegment, train: &LineSegment) -> bool {
+        let angle = (query.angle() - train.angle()).rem_euclid(360.0);
+        let (short, long) = if query.length() < train.length() { (query, train) } else { (train, query) };
+        self.max_angle.is_none_or(|max| angle.min(360.0 - angle) <= max)
+            && self.min_length_ratio.is_none_or(|min| short.length() >= min * long.length())
+            && self.min_overlap.is_none_or(|min| long.overlap(short) >= min * short.length())
+    }
+
+    /// Matches the descriptors of `query_lines` against those of `train_lines`.
+    ///
+    /// # Panics
+    ///
+    /// If there is not exactly one descriptor per line.
+    pub fn match_lines<D: Descriptor>(
+        &self,
+        query: &[D],
+        train: &[D],
+        query_lines: &[LineSegment],
+        train_lines: &[LineSegment],
+    ) -> Vec<Match> {
+        assert_eq!(query.len(), query_lines.len(), "one query descriptor per line");
+        assert_eq!(train.len(), train_lines.len(), "one train descriptor per line");
+
+        // Distances of the consistent candidates, `None` for the others.
+        let distances: Vec<Vec<Option<f32>>> = query
+            .iter()
+            .zip(query_lines)
+            .map(|(q, q_line)| {
+                train
+                    .iter()
+                    .zip(train_lines)
+                    .map(|(t, t_line)| self.is_consistent(q_line, t_line).then(|| q.distance(t)))
+                    .collect()
+            })
+            .collect();
+
+        let mut matches = vec![];
+        for (query_idx, row) in distances.iter().enumerate() {
+            let mut best: Option<Match> = None;
+            let mut second = f32::INFINITY;
+            for (train_idx, distance) in row.iter().enumerate() {
+                let Some(distance) = *distance else {
+                    continue;
+                };
+                match best {
+                    Some(b) if distance >= b.distance => second = second.min(distance),
+                    _ => {
+                        if let Some(b) = best {
+                            second = b.distance;
+                        }
+                        best = Some(Match::new(query_idx, train_idx, distance));
+                    }
+                }
+            }
+            let Some(best) = best else {
+                continue;
+            };
+
+            if self.ratio.is_some_and(|ratio| best.distance >= ratio * second)
+                || self.max_distance.is_some_and(|max| best.distance > max)
+            {
+                continue;
+            }
+            if self.cross_check {
+                let column = distances.iter().map(|row| row[best.train_idx]);
+                let mutual = column
+                    .enumerate()
+                    .filter_map(|(i, d)| d.map(|d| (i, d)))
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .is_some_and(|(i, _)| i == query_idx);
+                if !mutual {
+                    continue;
+                }
+            }
+            matches.push(best);
+        }
+        matches
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{binarize_lbd, compute_lbd_descriptor, BinaryLbdExtractor, LbdExtractor, LineGradients, LineMatcher};
+    use crate::detectors::LineDescriptorExtractor;
+    use crate::evaluation::{project, rotation_about, warp_perspective};
+    use crate::image::{gaussian_blur, noise_image, GrayFloatImage};
+    use crate::line::LineSegment;
+    use crate::matcher::Descriptor;
+
+    /// Bright rectangles of different sizes and contrasts on a textured background.
+    fn scene(size: u32) -> GrayFloatImage {
+        let mut image = noise_image(size, 4242);
+        for pixel in image.pixels_mut() {
+            pixel[0] = 0.2 + 0.1 * pixel[0];
+        }
+        for (x0, y0, x1, y1, value) in [(30, 30, 90, 70, 0.9), (110, 40, 170, 150, 0.6), (40, 110, 95, 170, 0.75)] {
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    image.put(x, y, value);
+                }
+            }
+        }
+        gaussian_blur(&image, 1.0)
+    }
+
+    /// Edges of the rectangles of [`scene`], oriented with the bright side on
+    /// the left like LSD.
+    fn scene_lines() -> Vec<LineSegment> {
+        vec![
+            LineSegment::new(35.0, 29.5, 85.0, 29.5),
+            LineSegment::new(89.5, 35.0, 89.5, 65.0),
+            LineSegment::new(115.0, 149.5, 165.0, 149.5),
+            LineSegment::new(109.5, 145.0, 109.5, 45.0),
+            LineSegment::new(169.5, 45.0, 169.5, 145.0),
+            LineSegment::new(45.0, 109.5, 90.0, 109.5),
+            LineSegment::new(39.5, 165.0, 39.5, 115.0),
+        ]
+    }
+
+    #[test]
+    fn descriptor_is_normalised_and_rotation_invariant() {
+        let size = 200;
+        let image = scene(size);
+        let center = size as f32 / 2.0 - 0.5;
+        let transform = rotation_about(10.0, center, center);
+        let rotated = warp_perspective(&image, &transform, size, size);
+        let line = scene_lines()[3];
+        let ((x1, y1), (x2, y2)) = (
+            project(&transform, line.x1, line.y1).unwrap(),
+            project(&transform, line.x2, line.y2).unwrap(),
+        );
+
+        let gradients = LineGradients::new(&image);
+        let a = compute_lbd_descriptor(&gradients, &line);
+        let b = compute_lbd_descriptor(&LineGradients::new(&rotated), &LineSegment::new(x1, y1, x2, y2));
+        let reversed = compute_lbd_descriptor(&gradients, &line.reversed());
+        let other = compute_lbd_descriptor(&gradients, &scene_lines()[0]);
+
+        assert!((a[..36].iter().map(|v| v * v).sum::<f32>() - 1.0).abs() < 1e-4);
+        assert!((a[36..].iter().map(|v| v * v).sum::<f32>() - 1.0).abs() < 1e-4);
+        let same = a.distance(&b);
+        assert!(same < 0.5 * a.distance(&other), "rotated {} other {}", same, a.distance(&other));
+        assert!(same < 0.5 * a.distance(&reversed), "rotated {} reversed {}", same, a.distance(&reversed));
+        assert!(binarize_lbd(&a).distance(&binarize_lbd(&b)) < binarize_lbd(&a).distance(&binarize_lbd(&other)));
+    }
+
+    #[test]
+    fn geometric_checks() {
+        let matcher = LineMatcher { min_overlap: Some(0.5), ..LineMatcher::default() };
+        let line = LineSegment::new(0.0, 0.0, 100.0, 0.0);
+        assert!(matcher.is_consistent(&line, &LineSegment::new(10.0, 5.0, 90.0, 8.0)));
+        assert!(!matcher.is_consistent(&line, &LineSegment::new(90.0, 8.0, 10.0, 5.0)));
+        assert!(!matcher.is_consistent(&line, &LineSegment::new(0.0, 0.0, 100.0, 60.0)));
+        assert!(!matcher.is_consistent(&line, &LineSegment::new(10.0, 5.0, 40.0, 5.0)));
+        assert!(!matcher.is_consistent(&line, &LineSegment::new(80.0, 5.0, 160.0, 5.0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "one train descriptor per line")]
+    fn descriptors_must_match_the_lines() {
+        let lines = [LineSegment::new(0.0, 0.0, 10.0, 0.0), LineSegment::new(0.0, 5.0, 10.0, 5.0)];
+        LineMatcher::default().match_lines(&[[0.0f32; 2]; 2], &[[0.0f32; 2]], &lines, &lines);
+    }
+
+    #[test]
+    fn matches_lines_between_frames() {
+        let image = scene(200);
+        let lines = scene_lines();
+        // The second frame is shifted by a few pixels.
+        let mut shifted = GrayFloatImage::new(200, 200);
+        for y in 0..200 {
+            for x in 0..200 {
+                shifted.put(x, y, image.sample_bilinear(x as f32 - 3.0, y as f32 + 2.0));
+            }
+        }
+        let shifted_lines: Vec<LineSegment> = lines
+            .iter()
+            .map(|l| LineSegment::new(l.x1 + 3.0, l.y1 - 2.0, l.x2 + 3.0, l.y2 - 2.0))
+            .rev()
+            .collect();
+
+        let float = LbdExtractor;
+        let matches = LineMatcher::default().match_lines(
+            &float.compute_lines(&image, &lines),
+            &float.compute_lines(&shifted, &shifted_lines),
+            &lines,
+            &shifted_lines,
+        );
+        assert_eq!(matches.len(), lines.len(), "{:?}", matches);
+        assert!(matches.iter().all(|m| m.train_idx == lines.len() - 1 - m.query_idx), "{:?}", matches);
+
+        let binary = BinaryLbdExtractor;
+        let matches = LineMatcher { ratio: None, ..LineMatcher::default() }.match_lines(
+            &binary.compute_lines(&image, &lines),
+            &binary.compute_lines(&shifted, &shifted_lines),
+            &lines,
+            &shifted_lines,
+        );
+        let correct = matches.iter().filter(|m| m.train_idx == lines.len() - 1 - m.query_idx).count();
+        assert!(correct >= lines.len() - 1, "{:?}", matches);
+    }
+}
@@ -1,27 +1,142 @@
+pub mod brisk;
+pub mod descriptors;
+pub mod detectors;
+pub mod edlines;
+pub mod evaluation;
+pub mod feature_cache;
+pub mod hamming;
+pub mod harris;
+pub mod image;
+mod io;
+pub mod kdtree;
+pub mod lbd;
+pub mod line;
+pub mod lsd;
+pub mod lsh;
+pub mod matcher;
+pub mod orb;
+pub mod sift;
+pub mod vanishing;
+pub mod vocabulary;
+
+use descriptors::{Corner, PATCH_SIZE};
+
+/// A detected feature. `x` and `y` are sub-pixel coordinates in the frame of the
+/// image the keypoint currently refers to, either level 0 or the pyramid level
+/// `octave`; `size` is the diameter of the described patch in level 0 pixels and
+/// `angle` the patch orientation in degrees in `[0, 360)`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KeyPoint {
-    pub point: (f32, f32)
+    pub x: f32,
+    pub y: f32,
+    pub size: f32,
+    pub angle: f32,
+    pub response: f32,
+    pub octave: usize,
+    /// Free label, e.g. the id of the object or map point the keypoint belongs to;
+    /// -1 when unused.
+    pub class_id: i32,
+}
+
+impl KeyPoint {
+    pub fn new(x: f32, y: f32, size: f32) -> KeyPoint {
+        KeyPoint {
+            x,
+            y,
+            size,
+            angle: 0.0,
+            response: 0.0,
+            octave: 0,
+            class_id: -1,
+        }
+    }
+
+    pub fn from_corner(corner: &Corner, octave: usize) -> KeyPoint {
+        KeyPoint {
+            response: corner.score,
+            octave,
+            ..KeyPoint::new(corner.x as f32, corner.y as f32, PATCH_SIZE as f32)
+        }
+    }
+
+    pub fn pt(&self) -> (f32, f32) {
+        (self.x, self.y)
+    }
+
+    /// Scale of the keypoint's octave relative to level 0, for pyramids whose
+    /// consecutive levels differ by `scale_factor`.
+    pub fn octave_scale(&self, scale_factor: f32) -> f32 {
+        scale_factor.powi(self.octave as i32)
+    }
+
+    /// Keypoint in level 0 coordinates moved to the frame of its octave.
+    pub fn to_octave(&self, scale_factor: f32) -> KeyPoint {
+        let scale = self.octave_scale(scale_factor);
+        KeyPoint { x: self.x / scale, y: self.y / scale, ..*self }
+    }
+
+    /// Keypoint in the frame of its octave moved to level 0 coordinates.
+    pub fn to_level_zero(&self, scale_factor: f32) -> KeyPoint {
+        let scale = self.octave_scale(scale_factor);
+        KeyPoint { x: self.x * scale, y: self.y * scale, ..*self }
+    }
+}
+
+/// A `(x, y)` corner as returned by [`harris::Harris::corner_detector`].
+impl From<(usize, usize)> for KeyPoint {
+    fn from((x, y): (usize, usize)) -> KeyPoint {
+        KeyPoint::new(x as f32, y as f32, PATCH_SIZE as f32)
+    }
+}
+
+impl From<&Corner> for KeyPoint {
+    fn from(corner: &Corner) -> KeyPoint {
+        KeyPoint::from_corner(corner, 0)
+    }
 }
 
+impl From<Corner> for KeyPoint {
+    fn from(corner: Corner) -> KeyPoint {
+        KeyPoint::from_corner(&corner, 0)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::KeyPoint;
+    use crate::descriptors::Corner;
 
+    #[test]
+    fn conversions() {
+        let harris = KeyPoint::from((12, 7));
+        assert_eq!(harris.pt(), (12.0, 7.0));
+        assert_eq!(harris.class_id, -1);
 
-/// Image opening and processing/manipulation
-pub mod image {
-    /// Re-export of [`image`] to open and save images
-    #[cfg(feature = "image")]
-    #[allow(clippy::module_inception)]
-    pub mod image {
-        pub use image::*;
+        let fast = KeyPoint::from(Corner::new(3, 4, 25.0));
+        assert_eq!((fast.pt(), fast.response, fast.octave), ((3.0, 4.0), 25.0, 0));
+        assert_eq!(KeyPoint::from_corner(&Corner::new(3, 4, 25.0), 2).octave, 2);
     }
 
-    /// Re-export of [`imageproc`] crate for image manipulation routines
-    #[cfg(feature = "imageproc")]
-    pub mod imageproc {
-        pub use imageproc::*;
+    #[test]
+    fn rescale_between_levels() {
+        let keypoint = KeyPoint { octave: 2, ..KeyPoint::new(144.0, 72.0, 31.0) };
+        assert!((keypoint.octave_scale(1.2) - 1.44).abs() < 1e-6);
+
+        let level = keypoint.to_octave(1.2);
+        assert!((level.x - 100.0).abs() < 1e-4 && (level.y - 50.0).abs() < 1e-4);
+        assert_eq!(level.size, keypoint.size);
+
+        let back = level.to_level_zero(1.2);
+        assert!((back.x - keypoint.x).abs() < 1e-4 && (back.y - keypoint.y).abs() < 1e-4);
     }
 
-    /// Re-export of [`ndarray-vision`] for image manipulation routines
-    #[cfg(feature = "ndarray-vision")]
-    pub mod ndarray_vision {
-        pub use ndarray_vision::*;
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let keypoint = KeyPoint { angle: 45.0, class_id: 9, ..KeyPoint::new(1.5, 2.5, 31.0) };
+        let json = serde_json::to_string(&keypoint).unwrap();
+        assert_eq!(serde_json::from_str::<KeyPoint>(&json).unwrap(), keypoint);
     }
-}
\ No newline at end of file
+}
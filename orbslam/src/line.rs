@@ -0,0 +1,710 @@
+use rand::{rngs::StdRng, seq::index, SeedableRng};
+
+/// A detected line segment in sub-pixel image coordinates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineSegment {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+    /// Width of the supporting region, in pixels.
+    pub width: f32,
+    /// Significance of the segment as `-log10(NFA)`, 0 when the detector does not
+    /// validate segments.
+    pub log_nfa: f32,
+    /// Pyramid level the segment was detected at, 0 for single scale detectors.
+    pub octave: usize,
+}
+
+impl LineSegment {
+    pub fn new(x1: f32, y1: f32, x2: f32, y2: f32) -> LineSegment {
+        LineSegment { x1, y1, x2, y2, width: 1.0, log_nfa: 0.0, octave: 0 }
+    }
+
+    /// Scale of the segment's octave relative to level 0, for pyramids whose
+    /// consecutive levels differ by `scale_factor`.
+    pub fn octave_scale(&self, scale_factor: f32) -> f32 {
+        scale_factor.powi(self.octave as i32)
+    }
+
+    /// Segment in the frame of its octave moved to level 0 coordinates. Unlike
+    /// [`crate::KeyPoint::to_level_zero`] the pixel centers of both levels are
+    /// aligned, as they are by [`crate::image::resize`], since the half pixel
+    /// offset grows to several pixels on coarse levels.
+    pub fn to_level_zero(&self, scale_factor: f32) -> LineSegment {
+        let scale = self.octave_scale(scale_factor);
+        let at = |v: f32| (v + 0.5) * scale - 0.5;
+        LineSegment {
+            x1: at(self.x1),
+            y1: at(self.y1),
+            x2: at(self.x2),
+            y2: at(self.y2),
+            width: self.width * scale,
+            ..*self
+        }
+    }
+
+    pub fn start(&self) -> (f32, f32) {
+        (self.x1, self.y1)
+    }
+
+    pub fn end(&self) -> (f32, f32) {
+        (self.x2, self.y2)
+    }
+
+    /// The same segment from `end` to `start`.
+    pub fn reversed(&self) -> LineSegment {
+        LineSegment { x1: self.x2, y1: self.y2, x2: self.x1, y2: self.y1, ..*self }
+    }
+
+    pub fn length(&self) -> f32 {
+        (self.x2 - self.x1).hypot(self.y2 - self.y1)
+    }
+
+    pub fn midpoint(&self) -> (f32, f32) {
+        ((self.x1 + self.x2) / 2.0, (self.y1 + self.y2) / 2.0)
+    }
+
+    /// Unit vector from `start` to `end`, zero for a degenerate segment.
+    pub fn direction(&self) -> (f32, f32) {
+        let length = self.length();
+        if length > 0.0 {
+            ((self.x2 - self.x1) / length, (self.y2 - self.y1) / length)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+
+    /// Angle of the direction from `start` to `end` in degrees in `[0, 360)`, like
+    /// [`crate::KeyPoint::angle`].
+    pub fn angle(&self) -> f32 {
+        let angle = (self.y2 - self.y1).atan2(self.x2 - self.x1).to_degrees();
+        if angle < 0.0 {
+            angle + 360.0
+        } else {
+            angle
+        }
+    }
+
+    /// Coefficients `[a, b, c]` of the supporting line `a x + b y + c = 0`, the
+    /// cross product of the homogeneous endpoints, scaled so that `a^2 + b^2 = 1`
+    /// and `a x + b y + c` is the signed distance to the line.
+    pub fn homogeneous(&self) -> [f32; 3] {
+        let (a, b, c) = (self.y1 - self.y2, self.x2 - self.x1, self.x1 * self.y2 - self.x2 * self.y1);
+        let norm = a.hypot(b);
+        if norm > 0.0 {
+            [a / norm, b / norm, c / norm]
+        } else {
+            [a, b, c]
+        }
+    }
+
+    /// Distance from `(x, y)` to the infinite supporting line.
+    pub fn line_distance(&self, x: f32, y: f32) -> f32 {
+        let [a, b, c] = self.homogeneous();
+        (a * x + b * y + c).abs()
+    }
+
+    /// Distance from `(x, y)` to the closest point of the segment.
+    pub fn distance(&self, x: f32, y: f32) -> f32 {
+        let (px, py) = self.closest_point(x, y);
+        (x - px).hypot(y - py)
+    }
+
+    /// Position of the projection of `(x, y)` on the supporting line, in pixels
+    /// from `start` along the direction.
+    pub fn project(&self, x: f32, y: f32) -> f32 {
+        let (dx, dy) = self.direction();
+        (x - self.x1) * dx + (y - self.y1) * dy
+    }
+
+    /// Closest point of the segment to `(x, y)`.
+    pub fn closest_point(&self, x: f32, y: f32) -> (f32, f32) {
+        let t = self.project(x, y).clamp(0.0, self.length());
+        let (dx, dy) = self.direction();
+        (self.x1 + t * dx, self.y1 + t * dy)
+    }
+
+    /// Length of the overlap of the two segments once `other` is projected on the
+    /// supporting line of `self`, 0 when the projections are disjoint.
+    pub fn overlap(&self, other: &LineSegment) -> f32 {
+        let (t1, t2) = (self.project(other.x1, other.y1), self.project(other.x2, other.y2));
+        let (lo, hi) = (t1.min(t2).max(0.0), t1.max(t2).min(self.length()));
+        (hi - lo).max(0.0)
+    }
+
+    /// Intersection of the supporting lines, `None` when they are parallel.
+    pub fn line_intersection(&self, other: &LineSegment) -> Option<(f32, f32)> {
+        let ([a1, b1, c1], [a2, b2, c2]) = (self.homogeneous(), other.homogeneous());
+        let w = a1 * b2 - a2 * b1;
+        if w.abs() < 1e-6 {
+            return None;
+        }
+        Some(((b1 * c2 - b2 * c1) / w, (a2 * c1 - a1 * c2) / w))
+    }
+
+    /// Intersection of the two segments, `None` when they do not cross.
+    pub fn intersection(&self, other: &LineSegment) -> Option<(f32, f32)> {
+        const EPSILON: f32 = 1e-3;
+        let (x, y) = self.line_intersection(other)?;
+        let on = |s: &LineSegment| (-EPSILON..=s.length() + EPSILON).contains(&s.project(x, y));
+        (on(self) && on(other)).then_some((x, y))
+    }
+
+    /// Part of the segment inside the pixel area of a `width` x `height` image,
+    /// `[-0.5, width - 0.5] x [-0.5, height - 0.5]` with pixel centers at integer
+    /// coordinates, or `None` when the segment is outside (Liang-Barsky).
+    pub fn clip(&self, width: usize, height: usize) -> Option<LineSegment> {
+        let (dx, dy) = (self.x2 - self.x1, self.y2 - self.y1);
+        let (x_max, y_max) = (width as f32 - 0.5, height as f32 - 0.5);
+        let (mut t0, mut t1) = (0f32, 1f32);
+        // Each boundary as `p * t <= q`.
+        for (p, q) in [(-dx, self.x1 + 0.5), (dx, x_max - self.x1), (-dy, self.y1 + 0.5), (dy, y_max - self.y1)] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+                continue;
+            }
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+
+        Some(LineSegment {
+            x1: self.x1 + t0 * dx,
+            y1: self.y1 + t0 * dy,
+            x2: self.x1 + t1 * dx,
+            y2: self.y1 + t1 * dy,
+            ..*self
+        })
+    }
+}
+
+
+/// Total least squares line through `points`, optionally weighted (e.g. by
+/// gradient magnitude): the line through the weighted centroid along the
+/// principal axis of the weighted covariance, which minimises the sum of squared
+/// perpendicular distances whatever the line orientation. The endpoints are the
+/// projections of the extreme points, ordered like the first and last points,
+/// and the width is the extent of the points across the line. Returns `None` for
+/// less than two points or a zero total weight.
+///
+/// # Panics
+///
+/// If `weights` are given but not one per point.
+pub fn fit_line(points: &[(f32, f32)], weights: Option<&[f32]>) -> Option<LineSegment> {
+    if let Some(weights) = weights {
+        assert_eq!(weights.len(), points.len(), "one weight per point");
+    }
+    if points.len() < 2 {
+        return None;
+    }
+    let weight = |i: usize| weights.map_or(1.0, |w| w[i] as f64);
+
+    let (mut sum, mut cx, mut cy) = (0f64, 0f64, 0f64);
+    for (i, &(x, y)) in points.iter().enumerate() {
+        sum += weight(i);
+        cx += weight(i) * x as f64;
+        cy += weight(i) * y as f64;
+    }
+    if sum <= 0.0 {
+        return None;
+    }
+    (cx, cy) = (cx / sum, cy / sum);
+
+    let (mut sxx, mut syy, mut sxy) = (0f64, 0f64, 0f64);
+    for (i, &(x, y)) in points.iter().enumerate() {
+        let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+        sxx += weight(i) * dx * dx;
+        syy += weight(i) * dy * dy;
+        sxy += weight(i) * dx * dy;
+    }
+    let theta = 0.5 * (2.0 * sxy).atan2(sxx - syy);
+    let (mut dy, mut dx) = theta.sin_cos();
+
+    let along = |&(x, y): &(f32, f32)| (x as f64 - cx) * dx + (y as f64 - cy) * dy;
+    if along(&points[0]) > along(&points[points.len() - 1]) {
+        (dx, dy) = (-dx, -dy);
+    }
+
+    let (mut t_min, mut t_max, mut w_min, mut w_max) = (f64::INFINITY, f64::NEG_INFINITY, 0f64, 0f64);
+    for (i, &(x, y)) in points.iter().enumerate() {
+        if weight(i) <= 0.0 {
+            continue;
+        }
+        let (ox, oy) = (x as f64 - cx, y as f64 - cy);
+        let (t, w) = (ox * dx + oy * dy, -ox * dy + oy * dx);
+        (t_min, t_max) = (t_min.min(t), t_max.max(t));
+        (w_min, w_max) = (w_min.min(w), w_max.max(w));
+    }
+
+    Some(LineSegment {
+        width: (w_max - w_min).max(1.0) as f32,
+        ..LineSegment::new(
+            (cx + t_min * dx) as f32,
+            (cy + t_min * dy) as f32,
+            (cx + t_max * dx) as f32,
+            (cy + t_max * dy) as f32,
+        )
+    })
+}
+
+/// Parameters of [`fit_line_ransac`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LineRansacParams {
+    /// Points closer than this to a candidate line are its inliers, in pixels.
+    pub distance_threshold: f32,
+    pub iterations: usize,
+    pub seed: u64,
+}
+
+impl Default for LineRansacParams {
+    fn default() -> Self {
+        LineRansacParams {
+            distance_threshold: 1.0,
+            iterations: 100,
+            seed: 0,
+        }
+    }
+}
+
+/// Line robust to outlier points: the line through two random points with the
+/// most inliers (by total weight) is refined with [`fit_line`] on its inliers.
+/// Returns the line and the indices of the inliers.
+///
+/// # Panics
+///
+/// If `weights` are given but not one per point.
+pub fn fit_line_ransac(
+    points: &[(f32, f32)],
+    weights: Option<&[f32]>,
+    params: &LineRansacParams,
+) -> Option<(LineSegment, Vec<usize>)> {
+    if let Some(weights) = weights {
+        assert_eq!(weights.len(), points.len(), "one weight per point");
+    }
+    if points.len() < 2 {
+        return None;
+    }
+    let weight = |i: usize| weights.map_or(1.0, |w| w[i]);
+    let inliers_of = |line: &LineSegment| -> Vec<usize> {
+        (0..points.len())
+            .filter(|&i| line.line_distance(points[i].0, points[i].1) <= params.distance_threshold)
+            .collect()
+    };
+
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let mut best: Option<(f32, Vec<usize>)> = None;
+    for _ in 0..params.iterations {
+        let sample = index::sample(&mut rng, points.len(), 2);
+        let ((x1, y1), (x2, y2)) = (points[sample.index(0)], points[sample.index(1)]);
+        if x1 == x2 && y1 == y2 {
+            continue;
+        }
+        let inliers = inliers_of(&LineSegment::new(x1, y1, x2, y2));
+        let score: f32 = inliers.iter().map(|&i| weight(i)).sum();
+        if best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+            best = Some((score, inliers));
+        }
+    }
+
+    let (_, inliers) = best?;
+    let inlier_points: Vec<(f32, f32)> = inliers.iter().map(|&i| points[i]).collect();
+    let inlier_weights: Option<Vec<f32>> = weights.map(|_| inliers.iter().map(|&i| weight(i)).collect());
+    let line = fit_line(&inlier_points, inlier_weights.as_deref())?;
+    let inliers = inliers_of(&line);
+    Some((line, inliers))
+}
+
+
+/// Tolerances of [`merge_segments`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LineMergeParams {
+    /// Maximal angle between merged segments, in degrees.
+    pub max_angle: f32,
+    /// Maximal distance of the endpoints of a segment to the supporting line of
+    /// the other one, in pixels.
+    pub max_offset: f32,
+    /// Maximal gap between the segments along their common direction, in pixels.
+    pub max_gap: f32,
+    /// Also merge segments of opposite directions. Detectors such as LSD orient
+    /// segments by their contrast, so those usually belong to different edges.
+    pub ignore_polarity: bool,
+}
+
+impl Default for LineMergeParams {
+    fn default() -> Self {
+        LineMergeParams {
+            max_angle: 3.0,
+            max_offset: 1.5,
+            max_gap: 10.0,
+            ignore_polarity: false,
+        }
+    }
+}
+
+/// The merge of `other` into `segment` when they are collinear within the
+/// tolerances, with `other` reversed first if its direction is opposite.
+fn merge_pair(segment: &LineSegment, other: &LineSegment, params: &LineMergeParams) -> Option<LineSegment> {
+    let angle = (other.angle() - segment.angle()).rem_euclid(360.0);
+    let other = if angle.min(360.0 - angle) <= params.max_angle {
+        *other
+    } else if params.ignore_polarity && (angle - 180.0).abs() <= params.max_angle {
+        other.reversed()
+    } else {
+        return None;
+    };
+
+    // Only the shorter segment is measured against the line of the longer one: the
+    // other way round, a small angle error of a short fragment is amplified by the
+    // length of the long segment.
+    let (long, short) = if segment.length() >= other.length() {
+        (segment, &other)
+    } else {
+        (&other, segment)
+    };
+    if long.line_distance(short.x1, short.y1) > params.max_offset
+        || long.line_distance(short.x2, short.y2) > params.max_offset
+    {
+        return None;
+    }
+
+    let (t1, t2) = (segment.project(other.x1, other.y1), segment.project(other.x2, other.y2));
+    let gap = (t1.min(t2) - segment.length()).max(-t1.max(t2));
+    if gap > params.max_gap {
+        return None;
+    }
+
+    // Total least squares line through the endpoints, weighted by the lengths.
+    let (l1, l2) = (segment.length(), other.length());
+    let points = [segment.start(), other.start(), other.end(), segment.end()];
+    let merged = fit_line(&points, Some(&[l1, l2, l2, l1]))?;
+    Some(LineSegment {
+        width: segment.width.max(other.width),
+        log_nfa: segment.log_nfa.max(other.log_nfa),
+        octave: segment.octave,
+        ..merged
+    })
+}
+
+/// Merges fragments of the same edge: segments with similar angles, small
+/// perpendicular offsets and small gaps between their ends are replaced by a
+/// single segment spanning them. Longer segments absorb shorter ones first and
+/// merging repeats until nothing changes, so chains of fragments are linked.
+///
+/// The merged segment keeps the direction and octave of the longer one, the
+/// largest width and the largest `-log10(NFA)` of its parts.
+pub fn merge_segments(segments: &[LineSegment], params: &LineMergeParams) -> Vec<LineSegment> {
+    let mut segments = segments.to_vec();
+    loop {
+        segments.sort_by(|a, b| b.length().total_cmp(&a.length()));
+        let mut merged_any = false;
+        let mut merged: Vec<LineSegment> = Vec::with_capacity(segments.len());
+        let mut absorbed = vec![false; segments.len()];
+
+        for i in 0..segments.len() {
+            if absorbed[i] {
+                continue;
+            }
+            let mut segment = segments[i];
+            for j in i + 1..segments.len() {
+                if absorbed[j] {
+                    continue;
+                }
+                if let Some(longer) = merge_pair(&segment, &segments[j], params) {
+                    segment = longer;
+                    absorbed[j] = true;
+                    merged_any = true;
+                }
+            }
+            merged.push(segment);
+        }
+
+        segments = merged;
+        if !merged_any {
+            return segments;
+        }
+    }
+}
+
+
+/// Tolerances of [`remove_scale_duplicates`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScaleDuplicateParams {
+    /// Maximal angle between a segment and the finer segments covering it, in
+    /// degrees.
+    pub max_angle: f32,
+    /// Maximal distance of the endpoints of the finer segments to the supporting
+    /// line of the coarser one, in pixels of the coarser octave.
+    pub max_offset: f32,
+    /// Fraction of the length of a segment that finer segments must cover for it
+    /// to be a duplicate.
+    pub min_coverage: f32,
+}
+
+impl Default for ScaleDuplicateParams {
+    fn default() -> Self {
+        ScaleDuplicateParams {
+            max_angle: 5.0,
+            max_offset: 1.5,
+            min_coverage: 0.7,
+        }
+    }
+}
+
+/// Removes the segments found again on coarser pyramid levels. Segments in
+/// level 0 coordinates are visited from the finest octave up and a segment is
+/// dropped when segments of finer octaves with the same direction lie on its line
+/// and cover enough of its length, so edges keep their most accurate detection
+/// while edges only found at coarse scales, or found in pieces at fine ones,
+/// survive.
+pub fn remove_scale_duplicates(
+    segments: &[LineSegment],
+    scale_factor: f32,
+    params: &ScaleDuplicateParams,
+) -> Vec<LineSegment> {
+    let mut segments = segments.to_vec();
+    segments.sort_by_key(|s| s.octave);
+
+    let mut kept: Vec<LineSegment> = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let max_offset = params.max_offset * segment.octave_scale(scale_factor);
+        let mut covered: Vec<(f32, f32)> = kept
+            .iter()
+            .filter(|finer| finer.octave < segment.octave)
+            .filter(|finer| {
+                let angle = (finer.angle() - segment.angle()).rem_euclid(360.0);
+                angle.min(360.0 - angle) <= params.max_angle
+                    && segment.line_distance(finer.x1, finer.y1) <= max_offset
+                    && segment.line_distance(finer.x2, finer.y2) <= max_offset
+            })
+            .map(|finer| {
+                let (t1, t2) = (segment.project(finer.x1, finer.y1), segment.project(finer.x2, finer.y2));
+                (t1.min(t2).max(0.0), t1.max(t2).min(segment.length()))
+            })
+            .filter(|(lo, hi)| hi > lo)
+            .collect();
+
+        // Length of the union of the covered intervals.
+        covered.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let (mut coverage, mut end) = (0.0, 0f32);
+        for (lo, hi) in covered {
+            coverage += (hi - lo.max(end)).max(0.0);
+            end = end.max(hi);
+        }
+
+        if coverage < params.min_coverage * segment.length() {
+            kept.push(segment);
+        }
+    }
+    kept
+}
+
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{
+        fit_line, fit_line_ransac, merge_segments, remove_scale_duplicates, LineMergeParams, LineRansacParams,
+        LineSegment, ScaleDuplicateParams,
+    };
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+    }
+
+    #[test]
+    fn basic_geometry() {
+        let segment = LineSegment::new(1.0, 1.0, 4.0, 5.0);
+        assert_eq!(segment.length(), 5.0);
+        assert_eq!(segment.midpoint(), (2.5, 3.0));
+        assert!(close(segment.direction(), (0.6, 0.8)));
+        assert!((segment.angle() - 53.1301).abs() < 1e-3);
+        assert!((segment.reversed().angle() - 233.1301).abs() < 1e-3);
+
+        let [a, b, c] = segment.homogeneous();
+        assert!((a * a + b * b - 1.0).abs() < 1e-6);
+        assert!((a * 1.0 + b * 1.0 + c).abs() < 1e-6 && (a * 4.0 + b * 5.0 + c).abs() < 1e-6);
+
+        // (5, -2) is 5 pixels from the line, beside the start point.
+        assert!((segment.line_distance(5.0, -2.0) - 5.0).abs() < 1e-5);
+        assert!((segment.distance(5.0, -2.0) - 5.0).abs() < 1e-5);
+        // Beyond the end the segment distance is to the endpoint.
+        assert!((segment.line_distance(7.0, 9.0)).abs() < 1e-5);
+        assert!((segment.distance(7.0, 9.0) - 5.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn overlap_and_intersection() {
+        let horizontal = LineSegment::new(0.0, 0.0, 10.0, 0.0);
+        assert_eq!(horizontal.overlap(&LineSegment::new(12.0, 1.0, 6.0, 2.0)), 4.0);
+        assert_eq!(horizontal.overlap(&LineSegment::new(11.0, 1.0, 14.0, 1.0)), 0.0);
+
+        let vertical = LineSegment::new(3.0, -2.0, 3.0, 2.0);
+        assert!(close(horizontal.intersection(&vertical).unwrap(), (3.0, 0.0)));
+        let short = LineSegment::new(3.0, 1.0, 3.0, 2.0);
+        assert!(horizontal.intersection(&short).is_none());
+        assert!(close(horizontal.line_intersection(&short).unwrap(), (3.0, 0.0)));
+        assert!(horizontal.line_intersection(&LineSegment::new(0.0, 1.0, 5.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn clip_to_image() {
+        let segment = LineSegment { width: 2.0, ..LineSegment::new(-10.0, 5.0, 30.0, 5.0) };
+        let clipped = segment.clip(20, 10).unwrap();
+        assert!(close(clipped.start(), (-0.5, 5.0)) && close(clipped.end(), (19.5, 5.0)));
+        assert_eq!(clipped.width, 2.0);
+
+        let diagonal = LineSegment::new(-5.5, -5.5, 4.5, 4.5).clip(10, 10).unwrap();
+        assert!(close(diagonal.start(), (-0.5, -0.5)) && close(diagonal.end(), (4.5, 4.5)));
+
+        assert!(LineSegment::new(-5.0, 20.0, 30.0, 20.0).clip(20, 10).is_none());
+        assert_eq!(LineSegment::new(1.0, 1.0, 2.0, 2.0).clip(20, 10), Some(LineSegment::new(1.0, 1.0, 2.0, 2.0)));
+    }
+
+    /// Points along a line through `(10, 20)` at `angle` degrees with uniform noise
+    /// across it.
+    fn noisy_line(rng: &mut StdRng, angle: f32, n: usize, noise: f32) -> Vec<(f32, f32)> {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        (0..n)
+            .map(|i| {
+                let (t, w) = (i as f32, rng.gen_range(-noise..=noise));
+                (10.0 + t * cos - w * sin, 20.0 + t * sin + w * cos)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn total_least_squares_at_any_angle() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for angle in [0.0, 30.0, 89.5, 90.0, 135.0, 200.0] {
+            let points = noisy_line(&mut rng, angle, 50, 0.3);
+            let line = fit_line(&points, None).unwrap();
+            let error = (line.angle() - angle).rem_euclid(360.0);
+            assert!(error.min(360.0 - error) < 1.0, "angle {} fitted {:?}", angle, line);
+            assert!((line.length() - 49.0).abs() < 1.0);
+            // Ordered like the points, the first one projects on the start.
+            assert!((line.start().0 - 10.0).hypot(line.start().1 - 20.0) < 0.5, "{:?}", line);
+            // The points spread 0.6 pixels across the line, less than the minimal width.
+            assert_eq!(line.width, 1.0);
+        }
+        assert!(fit_line(&[(1.0, 1.0)], None).is_none());
+    }
+
+    #[test]
+    fn weights_ignore_outliers() {
+        let mut points: Vec<(f32, f32)> = (0..20).map(|x| (x as f32, 5.0)).collect();
+        points.push((10.0, 40.0));
+        let mut weights = vec![1.0; 20];
+        weights.push(0.0);
+
+        let line = fit_line(&points, Some(&weights)).unwrap();
+        assert!(close(line.start(), (0.0, 5.0)) && close(line.end(), (19.0, 5.0)), "{:?}", line);
+        let unweighted = fit_line(&points, None).unwrap();
+        assert!(unweighted.line_distance(0.0, 5.0) > 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "one weight per point")]
+    fn weights_must_match_the_points() {
+        fit_line(&[(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)], Some(&[1.0, 1.0]));
+    }
+
+    #[test]
+    fn ransac_rejects_outliers() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut points = noisy_line(&mut rng, 60.0, 70, 0.3);
+        for _ in 0..30 {
+            points.push((rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0)));
+        }
+
+        let (line, inliers) = fit_line_ransac(&points, None, &LineRansacParams::default()).unwrap();
+        assert!((line.angle() - 60.0).abs() < 1.0, "{:?}", line);
+        assert!(inliers.iter().filter(|&&i| i < 70).count() == 70);
+        assert!(inliers.len() < 80);
+    }
+
+    #[test]
+    fn merges_collinear_fragments() {
+        let fragments = [
+            LineSegment::new(0.0, 10.0, 30.0, 10.2),
+            LineSegment::new(66.0, 10.5, 100.0, 10.6),
+            LineSegment::new(36.0, 10.3, 60.0, 10.4),
+            // Parallel but offset, at a different angle, and too far away.
+            LineSegment::new(0.0, 15.0, 30.0, 15.0),
+            LineSegment::new(0.0, 20.0, 30.0, 35.0),
+            LineSegment::new(120.0, 10.6, 150.0, 10.7),
+        ];
+        let merged = merge_segments(&fragments, &LineMergeParams::default());
+        assert_eq!(merged.len(), 4, "{:?}", merged);
+
+        let long = merged.iter().find(|s| s.length() > 90.0).unwrap();
+        assert!(long.x1 < 0.5 && long.x2 > 99.5, "{:?}", long);
+        assert!((long.y1 - 10.0).abs() < 0.5 && (long.y2 - 10.6).abs() < 0.5, "{:?}", long);
+    }
+
+    #[test]
+    fn short_tilted_fragments_merge_into_long_edges() {
+        // 0.5 degrees off and 0.26 pixels away at its far end: the fragment's own line
+        // passes 2.7 pixels away from the start of the edge.
+        let (sin, cos) = 0.5f32.to_radians().sin_cos();
+        let fragments = [
+            LineSegment::new(0.0, 0.0, 300.0, 0.0),
+            LineSegment::new(305.0, 0.0, 305.0 + 30.0 * cos, 30.0 * sin),
+        ];
+        let merged = merge_segments(&fragments, &LineMergeParams::default());
+        assert_eq!(merged.len(), 1, "{:?}", merged);
+        assert!(merged[0].x1 < 0.5 && merged[0].x2 > 334.5, "{:?}", merged);
+    }
+
+    #[test]
+    fn polarity_is_respected_unless_ignored() {
+        let fragments = [LineSegment::new(0.0, 0.0, 40.0, 0.0), LineSegment::new(70.0, 0.0, 45.0, 0.0)];
+        assert_eq!(merge_segments(&fragments, &LineMergeParams::default()).len(), 2);
+
+        let params = LineMergeParams { ignore_polarity: true, ..LineMergeParams::default() };
+        let merged = merge_segments(&fragments, &params);
+        assert_eq!(merged.len(), 1);
+        assert!(close(merged[0].start(), (0.0, 0.0)) && close(merged[0].end(), (70.0, 0.0)), "{:?}", merged);
+    }
+
+    #[test]
+    fn octave_to_level_zero() {
+        let segment = LineSegment { octave: 2, width: 1.5, ..LineSegment::new(-0.5, 0.0, 9.5, 4.0) };
+        let level_zero = segment.to_level_zero(2.0);
+        assert!(close(level_zero.start(), (-0.5, 1.5)) && close(level_zero.end(), (39.5, 17.5)), "{:?}", level_zero);
+        assert_eq!(level_zero.width, 6.0);
+        assert_eq!(level_zero.octave, 2);
+    }
+
+    #[test]
+    fn coarse_duplicates_are_removed() {
+        let at = |octave: usize, x1: f32, y1: f32, x2: f32, y2: f32| LineSegment {
+            octave,
+            ..LineSegment::new(x1, y1, x2, y2)
+        };
+        let segments = [
+            // Found at every octave, the coarse ones slightly displaced.
+            at(2, 0.0, 52.0, 100.0, 51.0),
+            at(0, 0.0, 50.0, 100.0, 50.0),
+            at(1, 1.0, 51.0, 99.0, 50.5),
+            // Fragmented at level 0 but complete at octave 1.
+            at(0, 0.0, 100.0, 30.0, 100.0),
+            at(0, 60.0, 100.0, 80.0, 100.0),
+            at(1, 0.0, 100.5, 100.0, 100.5),
+            // Opposite contrast at octave 1.
+            at(1, 100.0, 50.5, 0.0, 50.5),
+        ];
+        let kept = remove_scale_duplicates(&segments, 2.0, &ScaleDuplicateParams::default());
+        assert_eq!(kept, vec![segments[1], segments[3], segments[4], segments[5], segments[6]]);
+    }
+}
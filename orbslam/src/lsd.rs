@@ -1,14 +1,13 @@
-use std::f32::consts::PI;
-use image::imageops::FilterType;
-use imageproc::drawing::Canvas;
+use std::f32::consts::PI as PI_F32;
+use std::f64::consts::PI;
+
 use ndarray::Array2;
-use crate::image::{gaussian_blur, sobel_filter_x, sobel_filter_y, GrayFloatImage};
 
-#[derive(Debug, Clone, Copy)]
-pub struct Point {
-    pub x: usize,
-    pub y: usize
-}
+use crate::image::{sobel_filter_x, sobel_filter_y, GrayFloatImage};
+use crate::line::LineSegment;
+
+/// Level-line angle of pixels whose gradient is below the quantization threshold.
+const NOT_DEF: f64 = -1024.0;
 
 pub fn lsd_detector(image: &GrayFloatImage, threshold: f32) -> Array2<f32> {
     let i_x = sobel_filter_x(image);
@@ -29,73 +28,621 @@ pub fn lsd_detector(image: &GrayFloatImage, threshold: f32) -> Array2<f32> {
     detected_lines
 }
 
+/// Parameters of [`new_lsd_detector`], with the defaults of the reference
+/// implementation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LsdParams {
+    /// The image is Gaussian filtered and subsampled by this factor first.
+    pub scale: f64,
+    /// The Gaussian standard deviation is `sigma_scale / scale`.
+    pub sigma_scale: f64,
+    /// Bound on the gradient quantization error, in 8-bit intensity levels.
+    pub quant: f64,
+    /// Angle tolerance in degrees for a pixel to be aligned with a region.
+    pub ang_th: f64,
+    /// Segments are kept when `-log10(NFA) > log_eps`.
+    pub log_eps: f64,
+    /// Minimal proportion of region points in their rectangle, sparser regions
+    /// are refined.
+    pub density_th: f64,
+    /// Number of bins of the pseudo-ordering of seeds by gradient magnitude.
+    pub n_bins: usize,
+}
+
+impl Default for LsdParams {
+    fn default() -> Self {
+        LsdParams {
+            scale: 0.8,
+            sigma_scale: 0.6,
+            quant: 2.0,
+            ang_th: 22.5,
+            log_eps: 0.0,
+            density_th: 0.7,
+            n_bins: 1024,
+        }
+    }
+}
+
+/// LSD line segment detector (von Gioi et al., "LSD: a Line Segment Detector",
+/// IPOL 2012). Pixels are grouped into line-support regions sharing their
+/// level-line angle up to `ang_th`, each region is approximated by a rectangle
+/// and kept when its number of aligned points is unlikely under an a contrario
+/// noise model. Segments are returned in `image` coordinates with their width
+/// and `-log10(NFA)`.
+pub fn new_lsd_detector(image: &GrayFloatImage, params: &LsdParams) -> Vec<LineSegment> {
+    let scaled = gaussian_sampler(image, params.scale, params.sigma_scale);
+    let (height, width) = scaled.dim();
+
+    let prec = PI * params.ang_th / 180.0;
+    let p = params.ang_th / 180.0;
+    let level_lines = level_line_angles(&scaled, params.quant / prec.sin(), params.n_bins);
+
+    // Number of tests: every rectangle between two pixels with every width, times
+    // the number of precisions tried by the refinement.
+    let log_nt = 5.0 * ((width as f64).log10() + (height as f64).log10()) / 2.0 + 11f64.log10();
+    // Regions smaller than this cannot be meaningful even if fully aligned.
+    let min_region_size = (-log_nt / p.log10()) as usize;
+
+    let mut used = Array2::from_elem((height, width), false);
+    let mut segments = vec![];
+    for &(x, y) in level_lines.seeds.iter() {
+        if used[[y, x]] {
+            continue;
+        }
+
+        let (region, region_angle) = region_grow(x, y, &level_lines.angles, &mut used, prec);
+        if region.len() < min_region_size {
+            continue;
+        }
+
+        let rect = region_to_rect(&region, &level_lines.magnitude, region_angle, prec, p);
+        let Some((_, mut rect)) = refine(&level_lines, &mut used, region, rect, params.density_th) else {
+            continue;
+        };
+        let log_nfa = rect_improve(&mut rect, &level_lines.angles, log_nt, params.log_eps);
+        if log_nfa <= params.log_eps {
+            continue;
+        }
+
+        // The 2x2 gradient of pixel (x, y) is centered at (x + 0.5, y + 0.5).
+        let to_image = |v: f64| ((v + 0.5) / params.scale) as f32;
+        segments.push(LineSegment {
+            x1: to_image(rect.x1),
+            y1: to_image(rect.y1),
+            x2: to_image(rect.x2),
+            y2: to_image(rect.y2),
+            width: (rect.width / params.scale) as f32,
+            log_nfa: log_nfa as f32,
+            octave: 0,
+        });
+    }
+
+    segments
+}
 
-pub fn new_lsd_detector(image: &GrayFloatImage, threshold: f32) -> Vec<(Point, Point)> {
+/// Gaussian filtering and subsampling by `scale`. The kernel standard deviation
+/// is `sigma_scale / scale` to avoid aliasing and it is centered on the exact
+/// position `x / scale` of each output pixel. Intensities are returned in 8-bit
+/// levels, the unit of [`LsdParams::quant`].
+fn gaussian_sampler(image: &GrayFloatImage, scale: f64, sigma_scale: f64) -> Array2<f64> {
+    let (width, height) = (image.width(), image.height());
+    if scale == 1.0 {
+        return Array2::from_shape_fn((height, width), |(y, x)| image.get(x, y) as f64 * 255.0);
+    }
 
-    let gaussian_image = gaussian_blur(image, 2.0);
-    let scaled_image = scale_image(&gaussian_image, 0.8); 
+    let sigma = if scale < 1.0 { sigma_scale / scale } else { sigma_scale };
+    // Half size for which the discarded tail of the Gaussian is below 10^-3.
+    let half = (sigma * (6.0 * 10f64.ln()).sqrt()).ceil() as isize;
+    let (new_width, new_height) = ((width as f64 * scale).ceil() as usize, (height as f64 * scale).ceil() as usize);
+
+    let kernel = |offset: f64| -> Vec<f64> {
+        let mut kernel: Vec<f64> = (-half..=half)
+            .map(|i| {
+                let d = i as f64 - offset;
+                (-0.5 * d * d / (sigma * sigma)).exp()
+            })
+            .collect();
+        let sum: f64 = kernel.iter().sum();
+        kernel.iter_mut().for_each(|v| *v /= sum);
+        kernel
+    };
+
+    let mut horizontal = Array2::<f64>::zeros((height, new_width));
+    for x in 0..new_width {
+        let center = x as f64 / scale;
+        let xc = (center + 0.5).floor();
+        let kernel = kernel(center - xc);
+        for y in 0..height {
+            horizontal[[y, x]] = kernel
+                .iter()
+                .enumerate()
+                .map(|(i, k)| k * image.get(symmetric(xc as isize - half + i as isize, width), y) as f64)
+                .sum();
+        }
+    }
 
-    let i_x = sobel_filter_x(&scaled_image);
-    let i_y = sobel_filter_y(&scaled_image);
+    let mut sampled = Array2::<f64>::zeros((new_height, new_width));
+    for y in 0..new_height {
+        let center = y as f64 / scale;
+        let yc = (center + 0.5).floor();
+        let kernel = kernel(center - yc);
+        for x in 0..new_width {
+            sampled[[y, x]] = 255.0
+                * kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(i, k)| k * horizontal[[symmetric(yc as isize - half + i as isize, height), x]])
+                    .sum::<f64>();
+        }
+    }
 
-    let (magnitude, angle) = gradient_magnitude_direction(&i_x, &i_y);
+    sampled
+}
 
-    let (rows, cols) = (magnitude.shape()[0] ,magnitude.shape()[1]) ;
+/// Symmetric border extension with the edge pixel repeated.
+fn symmetric(i: isize, len: usize) -> usize {
+    let period = 2 * len as isize;
+    let i = i.rem_euclid(period);
+    if i >= len as isize {
+        (period - 1 - i) as usize
+    } else {
+        i as usize
+    }
+}
 
-    let mut clusters = Array2::<i32>::zeros((rows, cols));
-    let mut cluster_id = 1;
+/// Level-line angles and gradient magnitudes, indexed `[[y, x]]`.
+struct LevelLines {
+    angles: Array2<f64>,
+    magnitude: Array2<f64>,
+    /// Pixels with a defined angle, by decreasing gradient magnitude bins.
+    seeds: Vec<(usize, usize)>,
+}
 
-    for y in 0..rows {
-        for x in 0..cols {
-            if magnitude[[y, x]] > threshold && clusters[[y, x]] == 0 {
-                cluster_by_gradient_direction_single(&angle, &mut clusters, x as i32, y as i32, cluster_id, 4.0_f32.to_radians());
-                cluster_id += 1;
+/// Gradient of a 2x2 window at each pixel, the smallest support that keeps
+/// neighbouring gradients as independent as possible. The level-line angle is
+/// the gradient angle rotated by 90 degrees; it is left undefined where the
+/// magnitude is below `threshold`, and on the last row and column.
+fn level_line_angles(image: &Array2<f64>, threshold: f64, n_bins: usize) -> LevelLines {
+    let (height, width) = image.dim();
+    let mut angles = Array2::from_elem((height, width), NOT_DEF);
+    let mut magnitude = Array2::<f64>::zeros((height, width));
+
+    let mut max_gradient = 0f64;
+    for y in 0..height.saturating_sub(1) {
+        for x in 0..width.saturating_sub(1) {
+            let com1 = image[[y + 1, x + 1]] - image[[y, x]];
+            let com2 = image[[y, x + 1]] - image[[y + 1, x]];
+            let (gx, gy) = (com1 + com2, com1 - com2);
+            let norm = ((gx * gx + gy * gy) / 4.0).sqrt();
+            magnitude[[y, x]] = norm;
+            if norm > threshold {
+                angles[[y, x]] = gx.atan2(-gy);
+                max_gradient = max_gradient.max(norm);
             }
+        }
+    }
 
+    // Bucket sort: a full sort is not needed, only large gradients first.
+    let mut bins: Vec<Vec<(usize, usize)>> = vec![vec![]; n_bins.max(1)];
+    for ((y, x), angle) in angles.indexed_iter() {
+        if *angle != NOT_DEF {
+            let bin = ((magnitude[[y, x]] * n_bins as f64 / max_gradient) as usize).min(bins.len() - 1);
+            bins[bin].push((x, y));
         }
     }
+    let seeds = bins.into_iter().rev().flatten().collect();
+
+    LevelLines { angles, magnitude, seeds }
+}
+
+/// Whether a level-line angle is within `prec` of `theta`, both in radians.
+pub(crate) fn is_aligned(angle: f64, theta: f64, prec: f64) -> bool {
+    if angle == NOT_DEF {
+        return false;
+    }
+    let mut diff = (theta - angle).abs();
+    if diff > 1.5 * PI {
+        diff = (diff - 2.0 * PI).abs();
+    }
+    diff <= prec
+}
 
-    let total_pixels = (magnitude.shape()[0] * magnitude.shape()[1]) as usize;
-    let lines = extract_line_candidates(&clusters, total_pixels, threshold.into());
+/// Difference `a - b` of two angles in radians, in `(-pi, pi]`.
+fn angle_diff_signed(a: f64, b: f64) -> f64 {
+    let diff = (a - b).rem_euclid(2.0 * PI);
+    if diff > PI {
+        diff - 2.0 * PI
+    } else {
+        diff
+    }
+}
 
-    lines
+/// Absolute difference of two angles in radians, in `[0, pi]`.
+fn angle_diff(a: f64, b: f64) -> f64 {
+    angle_diff_signed(a, b).abs()
 }
 
-fn cluster_by_gradient_direction_single(
-    angle: &Array2<f32>, 
-    clusters: &mut Array2<i32>, 
-    x: i32, 
-    y: i32, 
-    cluster_id: i32, 
-    tolerance: f32
-) {
-    let rows = angle.shape()[0] as i32;
-    let cols = angle.shape()[1] as i32;
-    
+/// Grows a line-support region from a seed through 8-connected unused pixels
+/// aligned with the region angle, which is updated as the angle of the sum of
+/// the unit vectors of the region pixels.
+fn region_grow(x: usize, y: usize, angles: &Array2<f64>, used: &mut Array2<bool>, prec: f64) -> (Vec<(usize, usize)>, f64) {
+    let (height, width) = angles.dim();
     let mut region = vec![(x, y)];
-    let pixel_angle = angle[[y as usize, x as usize]];
-
-    while let Some((px, py)) = region.pop() {
-        if clusters[[py as usize, px as usize]] == 0 {
-            clusters[[py as usize, px as usize]] = cluster_id;
-            for dy in -1..=1 {
-                for dx in -1..=1 {
-                    if dx != 0 || dy != 0 {
-                        let nx = px + dx;
-                        let ny = py + dy;
-                        if nx >= 0 && nx < cols && ny >= 0 && ny < rows {
-                            let neighbor_angle = angle[[ny as usize, nx as usize]];
-                            if (neighbor_angle - pixel_angle).abs() <= tolerance && clusters[[ny as usize, nx as usize]] == 0 {
-                                region.push((nx, ny));
-                            }
-                        }
-                    }
+    used[[y, x]] = true;
+    let mut region_angle = angles[[y, x]];
+    let (mut sum_dx, mut sum_dy) = (region_angle.cos(), region_angle.sin());
+
+    let mut i = 0;
+    while i < region.len() {
+        let (px, py) = region[i];
+        for ny in py.saturating_sub(1)..=(py + 1).min(height - 1) {
+            for nx in px.saturating_sub(1)..=(px + 1).min(width - 1) {
+                let angle = angles[[ny, nx]];
+                if !used[[ny, nx]] && is_aligned(angle, region_angle, prec) {
+                    used[[ny, nx]] = true;
+                    region.push((nx, ny));
+                    sum_dx += angle.cos();
+                    sum_dy += angle.sin();
+                    region_angle = sum_dy.atan2(sum_dx);
+                }
+            }
+        }
+        i += 1;
+    }
+
+    (region, region_angle)
+}
+
+/// Rectangle approximating a line-support region, in the coordinates of the
+/// scaled image. `(x1, y1)` to `(x2, y2)` runs along `(dx, dy)`.
+#[derive(Copy, Clone, Debug)]
+struct Rect {
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+    width: f64,
+    theta: f64,
+    dx: f64,
+    dy: f64,
+    /// Angle tolerance of aligned points and the matching probability.
+    prec: f64,
+    p: f64,
+}
+
+/// The rectangle center is the centroid of the region weighted by gradient
+/// magnitude and its direction the principal axis of inertia; length and width
+/// are the smallest that contain every region pixel.
+fn region_to_rect(region: &[(usize, usize)], magnitude: &Array2<f64>, region_angle: f64, prec: f64, p: f64) -> Rect {
+    let (mut x, mut y, mut sum) = (0f64, 0f64, 0f64);
+    for &(px, py) in region {
+        let weight = magnitude[[py, px]];
+        x += px as f64 * weight;
+        y += py as f64 * weight;
+        sum += weight;
+    }
+    x /= sum;
+    y /= sum;
+
+    let theta = region_theta(region, magnitude, x, y, region_angle, prec);
+    let (dy, dx) = theta.sin_cos();
+
+    let (mut l_min, mut l_max, mut w_min, mut w_max) = (0f64, 0f64, 0f64, 0f64);
+    for &(px, py) in region {
+        let (ox, oy) = (px as f64 - x, py as f64 - y);
+        let l = ox * dx + oy * dy;
+        let w = -ox * dy + oy * dx;
+        l_min = l_min.min(l);
+        l_max = l_max.max(l);
+        w_min = w_min.min(w);
+        w_max = w_max.max(w);
+    }
+
+    Rect {
+        x1: x + l_min * dx,
+        y1: y + l_min * dy,
+        x2: x + l_max * dx,
+        y2: y + l_max * dy,
+        width: (w_max - w_min).max(1.0),
+        theta,
+        dx,
+        dy,
+        prec,
+        p,
+    }
+}
+
+/// Angle of the axis of least inertia of the region around `(x, y)`, turned
+/// around if needed to agree with the region angle.
+fn region_theta(region: &[(usize, usize)], magnitude: &Array2<f64>, x: f64, y: f64, region_angle: f64, prec: f64) -> f64 {
+    let (mut ixx, mut iyy, mut ixy) = (0f64, 0f64, 0f64);
+    for &(px, py) in region {
+        let weight = magnitude[[py, px]];
+        let (ox, oy) = (px as f64 - x, py as f64 - y);
+        ixx += oy * oy * weight;
+        iyy += ox * ox * weight;
+        ixy -= ox * oy * weight;
+    }
+
+    // Smallest eigenvalue of the inertia matrix.
+    let lambda = 0.5 * (ixx + iyy - ((ixx - iyy) * (ixx - iyy) + 4.0 * ixy * ixy).sqrt());
+    let theta = if ixx.abs() > iyy.abs() {
+        (lambda - ixx).atan2(ixy)
+    } else {
+        ixy.atan2(lambda - iyy)
+    };
+
+    if angle_diff(theta, region_angle) > prec {
+        theta + PI
+    } else {
+        theta
+    }
+}
+
+fn distance(x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+    (x2 - x1).hypot(y2 - y1)
+}
+
+/// Proportion of the rectangle area covered by region points.
+fn region_density(region: &[(usize, usize)], rect: &Rect) -> f64 {
+    region.len() as f64 / (distance(rect.x1, rect.y1, rect.x2, rect.y2) * rect.width)
+}
+
+/// Regions that fill less than `density_th` of their rectangle, typically two
+/// segments meeting at a small angle or a curve, are grown again from their seed
+/// with a tolerance of twice the standard deviation of the angles around the
+/// seed, then shrunk around it until dense enough. Returns `None` when the region
+/// vanishes; pixels dropped from the region are released for other seeds.
+fn refine(
+    level_lines: &LevelLines,
+    used: &mut Array2<bool>,
+    region: Vec<(usize, usize)>,
+    rect: Rect,
+    density_th: f64,
+) -> Option<(Vec<(usize, usize)>, Rect)> {
+    if region_density(&region, &rect) >= density_th {
+        return Some((region, rect));
+    }
+
+    let angles = &level_lines.angles;
+    let (xc, yc) = region[0];
+    let center_angle = angles[[yc, xc]];
+    let (mut sum, mut sum_squares, mut n) = (0f64, 0f64, 0f64);
+    for &(x, y) in region.iter() {
+        used[[y, x]] = false;
+        if distance(xc as f64, yc as f64, x as f64, y as f64) < rect.width {
+            let diff = angle_diff_signed(angles[[y, x]], center_angle);
+            sum += diff;
+            sum_squares += diff * diff;
+            n += 1.0;
+        }
+    }
+    let mean = sum / n;
+    let tau = 2.0 * ((sum_squares - 2.0 * mean * sum) / n + mean * mean).sqrt();
+
+    let (region, region_angle) = region_grow(xc, yc, angles, used, tau);
+    if region.len() < 2 {
+        return None;
+    }
+    let rect = region_to_rect(&region, &level_lines.magnitude, region_angle, rect.prec, rect.p);
+    reduce_region_radius(level_lines, used, region, region_angle, rect, density_th)
+}
+
+/// Removes the region points furthest from the seed, 25% of the radius at a
+/// time, until the region is dense enough.
+fn reduce_region_radius(
+    level_lines: &LevelLines,
+    used: &mut Array2<bool>,
+    mut region: Vec<(usize, usize)>,
+    region_angle: f64,
+    mut rect: Rect,
+    density_th: f64,
+) -> Option<(Vec<(usize, usize)>, Rect)> {
+    let (xc, yc) = (region[0].0 as f64, region[0].1 as f64);
+    let mut radius = distance(xc, yc, rect.x1, rect.y1).max(distance(xc, yc, rect.x2, rect.y2));
+
+    while region_density(&region, &rect) < density_th {
+        radius *= 0.75;
+        region.retain(|&(x, y)| {
+            let keep = distance(xc, yc, x as f64, y as f64) <= radius;
+            if !keep {
+                used[[y, x]] = false;
+            }
+            keep
+        });
+        if region.len() < 2 {
+            return None;
+        }
+        rect = region_to_rect(&region, &level_lines.magnitude, region_angle, rect.prec, rect.p);
+    }
+
+    Some((region, rect))
+}
+
+/// Tries variations of the rectangle and keeps the one with the best NFA, until
+/// it is meaningful: finer angle precisions, smaller widths, and each side moved
+/// inwards, then even finer precisions. Returns the new `-log10(NFA)`. These are
+/// the variations accounted for by the factor 11 of the number of tests.
+fn rect_improve(rect: &mut Rect, angles: &Array2<f64>, log_nt: f64, log_eps: f64) -> f64 {
+    let variations: [fn(&mut Rect) -> bool; 5] =
+        [finer_precision, reduce_width, |r| shift_side(r, 1.0), |r| shift_side(r, -1.0), finer_precision];
+
+    let mut log_nfa = rect_nfa(rect, angles, log_nt);
+    for change in variations {
+        if log_nfa > log_eps {
+            break;
+        }
+        let mut candidate = *rect;
+        for _ in 0..5 {
+            if change(&mut candidate) {
+                let candidate_nfa = rect_nfa(&candidate, angles, log_nt);
+                if candidate_nfa > log_nfa {
+                    log_nfa = candidate_nfa;
+                    *rect = candidate;
                 }
             }
         }
     }
+
+    log_nfa
+}
+
+/// Width step of the rectangle variations, in pixels.
+const RECT_DELTA: f64 = 0.5;
+
+fn finer_precision(rect: &mut Rect) -> bool {
+    rect.p /= 2.0;
+    rect.prec = rect.p * PI;
+    true
+}
+
+fn reduce_width(rect: &mut Rect) -> bool {
+    if rect.width - RECT_DELTA < 0.5 {
+        return false;
+    }
+    rect.width -= RECT_DELTA;
+    true
+}
+
+/// Moves one long side of the rectangle inwards: the side towards `(-dy, dx)`
+/// for a positive `sign`, the opposite one otherwise.
+fn shift_side(rect: &mut Rect, sign: f64) -> bool {
+    if !reduce_width(rect) {
+        return false;
+    }
+    let (sx, sy) = (-rect.dy * sign * RECT_DELTA / 2.0, rect.dx * sign * RECT_DELTA / 2.0);
+    rect.x1 += sx;
+    rect.y1 += sy;
+    rect.x2 += sx;
+    rect.y2 += sy;
+    true
+}
+
+/// `-log10(NFA)` of a rectangle: counts the pixels inside it and those aligned
+/// with its direction. Pixels outside the image are not counted.
+fn rect_nfa(rect: &Rect, angles: &Array2<f64>, log_nt: f64) -> f64 {
+    let (height, width) = angles.dim();
+    let half_width = rect.width / 2.0;
+    let length = (rect.x2 - rect.x1).hypot(rect.y2 - rect.y1);
+    let (nx, ny) = (-rect.dy * half_width, rect.dx * half_width);
+    let corners = [
+        (rect.x1 + nx, rect.y1 + ny),
+        (rect.x1 - nx, rect.y1 - ny),
+        (rect.x2 + nx, rect.y2 + ny),
+        (rect.x2 - nx, rect.y2 - ny),
+    ];
+    let x_min = corners.iter().map(|c| c.0).fold(f64::INFINITY, f64::min).ceil().max(0.0) as usize;
+    let x_max = corners.iter().map(|c| c.0).fold(f64::NEG_INFINITY, f64::max).floor();
+    let y_min = corners.iter().map(|c| c.1).fold(f64::INFINITY, f64::min).ceil().max(0.0) as usize;
+    let y_max = corners.iter().map(|c| c.1).fold(f64::NEG_INFINITY, f64::max).floor();
+    if x_max < 0.0 || y_max < 0.0 {
+        return nfa(0, 0, rect.p, log_nt);
+    }
+
+    let (mut n, mut k) = (0, 0);
+    for y in y_min..=(y_max as usize).min(height - 1) {
+        for x in x_min..=(x_max as usize).min(width - 1) {
+            let (ox, oy) = (x as f64 - rect.x1, y as f64 - rect.y1);
+            let l = ox * rect.dx + oy * rect.dy;
+            let w = -ox * rect.dy + oy * rect.dx;
+            if l < -1e-9 || l > length + 1e-9 || w.abs() > half_width + 1e-9 {
+                continue;
+            }
+            n += 1;
+            if is_aligned(angles[[y, x]], rect.theta, rect.prec) {
+                k += 1;
+            }
+        }
+    }
+
+    nfa(n, k, rect.p, log_nt)
+}
+
+/// Relative error accepted on `-log10(NFA)` when the binomial tail is cut short.
+const NFA_TOLERANCE: f64 = 0.1;
+
+/// `-log10(NFA)` of `k` aligned points out of `n`, each aligned with probability
+/// `p`, among `10^log_nt` tests:
+///
+/// `NFA = NT * sum_{i=k..n} C(n, i) p^i (1 - p)^(n - i)`
+///
+/// The first term of the binomial tail is computed in the log domain through
+/// [`ln_gamma`] and the others from it by recurrence. Once the terms decrease,
+/// the rest of the tail is bounded by a geometric series and the sum stops when
+/// that bound is within [`NFA_TOLERANCE`] of the result, which usually happens
+/// after a few terms instead of `n - k`.
+pub fn nfa(n: usize, k: usize, p: f64, log_nt: f64) -> f64 {
+    assert!(k <= n && p > 0.0 && p < 1.0, "invalid nfa arguments n={} k={} p={}", n, k, p);
+    if n == 0 || k == 0 {
+        return -log_nt;
+    }
+    if n == k {
+        return -log_nt - n as f64 * p.log10();
+    }
+
+    let (nf, kf) = (n as f64, k as f64);
+    let log_term = ln_gamma(nf + 1.0) - ln_gamma(kf + 1.0) - ln_gamma(nf - kf + 1.0) + kf * p.ln() + (nf - kf) * (1.0 - p).ln();
+    let mut term = log_term.exp();
+    if term == 0.0 {
+        // Beyond the mean the tail is dominated by its first term; below it the
+        // tail is close to 1.
+        return if kf > nf * p { -log_term / std::f64::consts::LN_10 - log_nt } else { -log_nt };
+    }
+
+    let p_term = p / (1.0 - p);
+    let mut tail = term;
+    for i in k + 1..=n {
+        // term(i) = term(i - 1) * (n - i + 1) / i * p / (1 - p)
+        let bin_term = (nf - i as f64 + 1.0) / i as f64;
+        let mult_term = bin_term * p_term;
+        term *= mult_term;
+        tail += term;
+
+        if bin_term < 1.0 {
+            // The following ratios are all below `mult_term`, so the remaining
+            // terms sum to less than this geometric series.
+            let error = term * ((1.0 - mult_term.powf(nf - i as f64 + 1.0)) / (1.0 - mult_term) - 1.0);
+            if error < NFA_TOLERANCE * (-tail.log10() - log_nt).abs() * tail {
+                break;
+            }
+        }
+    }
+    -tail.log10() - log_nt
 }
 
+/// `ln Γ(x)` for `x > 0`: Windschitl's approximation above 15, where it is more
+/// accurate, and Lanczos' below.
+pub fn ln_gamma(x: f64) -> f64 {
+    if x > 15.0 {
+        ln_gamma_windschitl(x)
+    } else {
+        ln_gamma_lanczos(x)
+    }
+}
+
+fn ln_gamma_lanczos(x: f64) -> f64 {
+    const Q: [f64; 7] = [
+        75122.6331530,
+        80916.6278952,
+        36308.2951477,
+        8687.24529705,
+        1168.92649479,
+        83.8676043424,
+        2.50662827511,
+    ];
+    let mut a = (x + 0.5) * (x + 5.5).ln() - (x + 5.5);
+    let mut b = 0.0;
+    for (n, q) in Q.iter().enumerate() {
+        a -= (x + n as f64).ln();
+        b += q * x.powi(n as i32);
+    }
+    a + b.ln()
+}
+
+fn ln_gamma_windschitl(x: f64) -> f64 {
+    // 0.5 * ln(2 pi)
+    0.918938533204673 + (x - 0.5) * x.ln() - x + 0.5 * x * (x * (1.0 / x).sinh() + 1.0 / (810.0 * x.powi(6))).ln()
+}
 
 fn gradient_magnitude_direction(i_x: &Array2<f32>, i_y: &Array2<f32>) -> (Array2<f32>, Array2<f32>) {
     let (height, width) = (i_x.shape()[0], i_x.shape()[1]);
@@ -107,73 +654,175 @@ fn gradient_magnitude_direction(i_x: &Array2<f32>, i_y: &Array2<f32>) -> (Array2
             let gx = i_x[[y, x]];
             let gy = i_y[[y, x]];
             magnitude[[y, x]] = (gx * gx + gy * gy).sqrt();
-            direction[[y, x]] = (gy.atan2(gx) * 180.0 / PI).abs();
+            direction[[y, x]] = (gy.atan2(gx) * 180.0 / PI_F32).abs();
         }
     }
 
     (magnitude, direction)
 }
 
-fn scale_image(image: &GrayFloatImage, scale: f32) -> GrayFloatImage {
-    let scaled_width = (image.width() as f32 * scale) as u32;
-    let scaled_height = (image.height() as f32 * scale) as u32;
-    let resized_image = image::imageops::resize(&image.0, scaled_width, scaled_height, FilterType::Lanczos3);
-    GrayFloatImage(resized_image)
-}
 
-fn fit_line(points: &[Point]) -> (Point, Point) {
-    let start = points[0];
-    let end = points[points.len() - 1];
-    (start, end)
-}
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
 
-fn extract_line_candidates(clusters: &Array2<i32>, total_pixels: usize, nfa_threshold: f64) -> Vec<(Point, Point)> {
-    let rows = clusters.shape()[0];
-    let cols = clusters.shape()[1];
-    let mut lines = Vec::new();
+    use ndarray::Array2;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
 
-    let max_cluster_id = *clusters.iter().max().unwrap();
+    use super::{
+        ln_gamma, new_lsd_detector, nfa, rect_improve, rect_nfa, refine, region_density, region_grow, region_to_rect,
+        LevelLines, LsdParams, Rect, NOT_DEF,
+    };
+    use crate::image::{noise_image, GrayFloatImage};
 
-    for cluster_id in 1..=max_cluster_id {
-        let mut points = Vec::new();
-        for y in 0..rows {
-            for x in 0..cols {
-                if clusters[[y, x]] == cluster_id {
-                    points.push(Point { x, y });
-                }
+    #[test]
+    fn detects_a_step_edge() {
+        let mut image = GrayFloatImage::new(200, 120);
+        for y in 0..120 {
+            for x in 0..200 {
+                let bright = (30..170).contains(&x) && (40..80).contains(&y);
+                image.put(x, y, if bright { 0.8 } else { 0.2 });
             }
         }
 
-        if points.len() > 1 {
-            let line = fit_line(&points);
-            let line_length = (((line.1.x - line.0.x).pow(2) + (line.1.y - line.0.y).pow(2)) as f64).sqrt();
-            let nfa = nfa_computation(points.len(), line_length, total_pixels);
-            println!("nfa is : {}", nfa);
+        let segments = new_lsd_detector(&image, &LsdParams::default());
+        // Two long horizontal edges at y = 39.5 and y = 79.5 and two short vertical ones.
+        let horizontal: Vec<_> = segments.iter().filter(|s| (s.y1 - s.y2).abs() < 1.0 && (s.x1 - s.x2).abs() > 100.0).collect();
+        assert_eq!(horizontal.len(), 2, "{:?}", segments);
+        for segment in horizontal {
+            let y = (segment.y1 + segment.y2) / 2.0;
+            assert!((y - 39.5).abs() < 1.0 || (y - 79.5).abs() < 1.0, "{:?}", segment);
+            assert!(segment.x1.min(segment.x2) < 35.0 && segment.x1.max(segment.x2) > 164.0, "{:?}", segment);
+            assert!(segment.log_nfa > 10.0);
+        }
+    }
+
+    #[test]
+    fn no_detections_in_noise() {
+        // The a contrario model expects less than one false detection per image.
+        let segments = new_lsd_detector(&noise_image(256, 4242), &LsdParams::default());
+        assert!(segments.len() <= 1, "{:?}", segments);
+    }
+
+    #[test]
+    fn nfa_of_trivial_cases() {
+        assert_eq!(nfa(0, 0, 0.125, 3.0), -3.0);
+        assert!((nfa(10, 10, 0.1, 0.0) - 10.0).abs() < 1e-9);
+        // P(X >= 1) for X ~ B(2, 0.5) is 3/4.
+        assert!((nfa(2, 1, 0.5, 0.0) + 0.75f64.log10()).abs() < 1e-6);
+    }
 
-            println!("fit line for cluster_id {}: {:?}", cluster_id, line);
-            if nfa < nfa_threshold {
-                lines.push(line);
+    /// `-log10` of the binomial tail `P(X >= k)` for `X ~ B(n, p)`, with exact
+    /// binomial coefficients.
+    fn exact_log_tail(n: u64, k: u64, p: f64) -> f64 {
+        let mut coefficient = 1u128;
+        let mut tail = 0f64;
+        for i in 0..=n {
+            if i > 0 {
+                coefficient = coefficient * (n - i + 1) as u128 / i as u128;
+            }
+            if i >= k {
+                tail += coefficient as f64 * p.powi(i as i32) * (1.0 - p).powi((n - i) as i32);
             }
         }
+        -tail.log10()
     }
-    lines
-}
 
-fn nfa_computation(num_points: usize, line_length: f64, total_pixels: usize) -> f64 {
-    let k = num_points as f64;
-    let n = total_pixels as f64;
-    let p = line_length / total_pixels as f64;
+    #[test]
+    fn ln_gamma_matches_factorials() {
+        let mut factorial = 1f64;
+        for n in 1..60 {
+            factorial *= n as f64;
+            let expected = factorial.ln();
+            assert!((ln_gamma(n as f64 + 1.0) - expected).abs() < 1e-6 * expected.max(1.0), "ln {}!", n);
+        }
+        // Γ(1/2) = sqrt(pi)
+        assert!((ln_gamma(0.5) - std::f64::consts::PI.sqrt().ln()).abs() < 1e-8);
+    }
 
-    let binomial_tail = (k as usize..=n as usize)
-        .map(|i| binomial_coefficient(n, i as f64) * p.powf(i as f64) * (1.0 - p).powf(n - i as f64))
-        .sum::<f64>();
+    #[test]
+    fn nfa_matches_exact_binomial_tail() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..2000 {
+            let n = rng.gen_range(1..=100u64);
+            let k = rng.gen_range(0..=n);
+            let p = [0.125, 1.0 / 16.0, 0.25, 0.5, rng.gen_range(0.01..0.99)][rng.gen_range(0..5)];
+            let log_nt = rng.gen_range(0.0..10.0);
+
+            let expected = exact_log_tail(n, k, p) - log_nt;
+            let value = nfa(n as usize, k as usize, p, log_nt);
+            // Early termination allows a 10% error on the tail relative to -log10(NFA).
+            let tolerance = 0.05 * expected.abs() + 1e-9;
+            assert!((value - expected).abs() <= tolerance, "n={} k={} p={}: {} vs {}", n, k, p, value, expected);
+        }
+    }
 
-    let nfa = n * binomial_tail;
-    nfa
-}
+    #[test]
+    fn nfa_is_monotonic_and_finite_for_large_regions() {
+        let mut previous = f64::NEG_INFINITY;
+        for k in (0..=2000).step_by(50) {
+            let value = nfa(2000, k, 0.125, 12.0);
+            assert!(value.is_finite());
+            assert!(value >= previous - 1e-9, "k={}", k);
+            previous = value;
+        }
 
-fn binomial_coefficient(n: f64, k: f64) -> f64 {
-    (0..k as usize).fold(1.0, |acc, i| acc * (n -1 as f64) / ( i as f64 + 1.0))
-}
+        // Far in the tail the terms underflow, the result is still finite.
+        let value = nfa(1_000_000, 900_000, 0.125, 12.0);
+        assert!(value.is_finite() && value > 1e5);
+    }
 
+    #[test]
+    fn rect_improve_rescues_a_wide_rectangle() {
+        // A single row of aligned pixels inside a rectangle four pixels wide.
+        let mut angles = Array2::from_elem((40, 40), NOT_DEF);
+        for x in 5..35 {
+            angles[[20, x]] = 0.0;
+        }
+        let mut rect = Rect {
+            x1: 5.0,
+            y1: 20.0,
+            x2: 34.0,
+            y2: 20.0,
+            width: 4.0,
+            theta: 0.0,
+            dx: 1.0,
+            dy: 0.0,
+            prec: PI / 8.0,
+            p: 0.125,
+        };
+
+        let log_nt = 5.0;
+        let initial = rect_nfa(&rect, &angles, log_nt);
+        let improved = rect_improve(&mut rect, &angles, log_nt, 0.0);
+        assert!(initial <= 0.0, "{}", initial);
+        assert!(improved > 0.0 && improved == rect_nfa(&rect, &angles, log_nt));
+        assert!(rect.p < 0.125 || rect.width < 4.0);
+    }
 
+    #[test]
+    fn refine_splits_diverging_lines() {
+        // Two lines leaving (5, 20) at 0 and 15 degrees, grown as a single sparse region.
+        let (mut angles, mut magnitude) = (Array2::from_elem((40, 60), NOT_DEF), Array2::zeros((40, 60)));
+        let slope = 15f64.to_radians();
+        for x in 5..50 {
+            let y = 20 + ((x - 5) as f64 * slope.tan()).round() as usize;
+            angles[[20, x]] = 0.0;
+            angles[[y, x]] = if y == 20 { 0.0 } else { slope };
+            magnitude[[20, x]] = 1.0;
+            magnitude[[y, x]] = 1.0;
+        }
+        let level_lines = LevelLines { angles, magnitude, seeds: vec![] };
+
+        let mut used = Array2::from_elem((40, 60), false);
+        let prec = PI / 8.0;
+        let (region, region_angle) = region_grow(5, 20, &level_lines.angles, &mut used, prec);
+        let rect = region_to_rect(&region, &level_lines.magnitude, region_angle, prec, 0.125);
+        assert!(region_density(&region, &rect) < 0.7);
+
+        let (refined, refined_rect) = refine(&level_lines, &mut used, region.clone(), rect, 0.7).unwrap();
+        assert!(refined.len() < region.len());
+        assert!(region_density(&refined, &refined_rect) >= 0.7);
+        assert_eq!(used.iter().filter(|u| **u).count(), refined.len());
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::{rngs::StdRng, seq::index, SeedableRng};
+
+use crate::hamming::hamming_distance;
+use crate::matcher::Match;
+
+/// Parameters of an [`LshIndex`], with the same meaning and defaults as FLANN's
+/// `LshIndexParams`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LshParams {
+    /// Number of hash tables.
+    pub table_number: usize,
+    /// Number of descriptor bits sampled to form a bucket key, at most 32.
+    pub key_size: usize,
+    /// Buckets whose key differs from the query key in up to this many bits are
+    /// also visited.
+    pub multi_probe_level: usize,
+    /// Seed for the choice of sampled bits.
+    pub seed: u64,
+}
+
+impl Default for LshParams {
+    fn default() -> Self {
+        LshParams {
+            table_number: 12,
+            key_size: 20,
+            multi_probe_level: 2,
+            seed: 0,
+        }
+    }
+}
+
+/// One hash table: the sampled bit positions and the buckets of descriptor ids.
+struct LshTable {
+    bits: Vec<usize>,
+    buckets: HashMap<u32, Vec<usize>>,
+}
+
+impl LshTable {
+    fn key<const N: usize>(&self, descriptor: &[u8; N]) -> u32 {
+        self.bits.iter().enumerate().fold(0, |key, (i, &bit)| {
+            key | ((((descriptor[bit / 8] >> (bit % 8)) & 1) as u32) << i)
+        })
+    }
+}
+
+/// Approximate nearest-neighbour index for binary descriptors of `N` bytes using
+/// multi-probe locality sensitive hashing on sampled bits (Lv et al., "Multi-probe
+/// LSH: efficient indexing for high-dimensional similarity search").
+///
+/// Ids stay valid until their descriptor is removed, after which the id is given
+/// to a later insertion.
+pub struct LshIndex<const N: usize> {
+    params: LshParams,
+    tables: Vec<LshTable>,
+    probes: Vec<u32>,
+    descriptors: Vec<Option<[u8; N]>>,
+    /// Slots of removed descriptors, reused by insertions.
+    free: Vec<usize>,
+    len: usize,
+}
+
+impl<const N: usize> LshIndex<N> {
+    pub fn new(params: LshParams) -> Self {
+        assert!(params.key_size <= 32 && params.key_size <= N * 8, "key_size too large");
+
+        let mut rng = StdRng::seed_from_u64(params.seed);
+        let tables = (0..params.table_number)
+            .map(|_| LshTable {
+                bits: index::sample(&mut rng, N * 8, params.key_size).into_vec(),
+                buckets: HashMap::new(),
+            })
+            .collect();
+
+        LshIndex {
+            params,
+            tables,
+            probes: probe_masks(params.key_size, params.multi_probe_level),
+            descriptors: vec![],
+            free: vec![],
+            len: 0,
+        }
+    }
+
+    /// Index over `descriptors`, whose ids are their positions in the slice.
+    pub fn build(descriptors: &[[u8; N]], params: LshParams) -> Self {
+        let mut index = LshIndex::new(params);
+        for descriptor in descriptors {
+            index.insert(*descriptor);
+        }
+        index
+    }
+
+    pub fn params(&self) -> &LshParams {
+        &self.params
+    }
+
+    /// Number of descriptors currently in the index.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, id: usize) -> Option<&[u8; N]> {
+        self.descriptors.get(id).and_then(|d| d.as_ref())
+    }
+
+    /// Adds a descriptor and returns its id, the slot of a removed descriptor if
+    /// there is one.
+    pub fn insert(&mut self, descriptor: [u8; N]) -> usize {
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.descriptors[id] = Some(descriptor);
+                id
+            }
+            None => {
+                self.descriptors.push(Some(descriptor));
+                self.descriptors.len() - 1
+            }
+        };
+        for table in self.tables.iter_mut() {
+            let key = table.key(&descriptor);
+            table.buckets.entry(key).or_default().push(id);
+        }
+        self.len += 1;
+        id
+    }
+
+    /// Removes a descriptor, returning false if the id is unknown or already removed.
+    pub fn remove(&mut self, id: usize) -> bool {
+        let Some(descriptor) = self.descriptors.get_mut(id).and_then(|d| d.take()) else {
+            return false;
+        };
+
+        for table in self.tables.iter_mut() {
+            let key = table.key(&descriptor);
+            if let Some(bucket) = table.buckets.get_mut(&key) {
+                bucket.retain(|&other| other != id);
+                if bucket.is_empty() {
+                    table.buckets.remove(&key);
+                }
+            }
+        }
+        self.free.push(id);
+        self.len -= 1;
+        true
+    }
+
+    /// Up to `k` candidates closest to `descriptor` as `(id, distance)`, closest
+    /// first. Only descriptors sharing a probed bucket are considered.
+    pub fn query(&self, descriptor: &[u8; N], k: usize) -> Vec<(usize, u32)> {
+        let mut visited = HashSet::new();
+        let mut candidates = vec![];
+
+        for table in self.tables.iter() {
+            let key = table.key(descriptor);
+            for probe in self.probes.iter() {
+                let Some(bucket) = table.buckets.get(&(key ^ probe)) else {
+                    continue;
+                };
+                for &id in bucket {
+                    if visited.insert(id) {
+                        if let Some(other) = &self.descriptors[id] {
+                            candidates.push((id, hamming_distance(descriptor, other)));
+                        }
+                    }
+                }
+            }
+        }
+
+        candidates.sort_unstable_by_key(|&(id, distance)| (distance, id));
+        candidates.truncate(k);
+        candidates
+    }
+
+    /// Approximate counterpart of [`crate::matcher::knn_match`], with the index ids
+    /// as `train_idx`.
+    pub fn knn_match(&self, queries: &[[u8; N]], k: usize) -> Vec<Vec<Match>> {
+        queries
+            .iter()
+            .enumerate()
+            .map(|(query_idx, query)| {
+                self.query(query, k)
+                    .into_iter()
+                    .map(|(id, distance)| Match::new(query_idx, id, distance as f32))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// All xor masks of `key_size` bits with at most `level` bits set, fewest bits first.
+fn probe_masks(key_size: usize, level: usize) -> Vec<u32> {
+    let mut masks = vec![0u32];
+    let mut frontier = vec![(0u32, 0usize)];
+    for _ in 0..level {
+        let mut next = vec![];
+        for (mask, first_free) in frontier {
+            for bit in first_free..key_size {
+                let probe = mask | (1 << bit);
+                masks.push(probe);
+                next.push((probe, bit + 1));
+            }
+        }
+        frontier = next;
+    }
+    masks
+}
+
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{probe_masks, LshIndex, LshParams};
+    use crate::matcher::knn_match;
+
+    fn noisy(rng: &mut StdRng, descriptor: &[u8; 32], bits: usize) -> [u8; 32] {
+        let mut noisy = *descriptor;
+        for _ in 0..bits {
+            let bit = rng.gen_range(0..256);
+            noisy[bit / 8] ^= 1 << (bit % 8);
+        }
+        noisy
+    }
+
+    #[test]
+    fn probe_masks_cover_hamming_ball() {
+        let masks = probe_masks(20, 2);
+        assert_eq!(masks.len(), 1 + 20 + 190);
+        assert!(masks.iter().all(|m| m.count_ones() <= 2));
+    }
+
+    #[test]
+    fn recall_against_exact_search() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let centers: Vec<[u8; 32]> = (0..500).map(|_| rng.gen()).collect();
+        let map: Vec<[u8; 32]> = (0..5000).map(|i| noisy(&mut rng, &centers[i % 500], 20)).collect();
+        let queries: Vec<[u8; 32]> = (0..300).map(|i| noisy(&mut rng, &map[i * 7], 20)).collect();
+
+        let index = LshIndex::build(&map, LshParams::default());
+        let approximate = index.knn_match(&queries, 1);
+        let exact = knn_match(&queries, &map, 1);
+
+        let hits = approximate
+            .iter()
+            .zip(exact.iter())
+            .filter(|(a, e)| a.first().map(|m| m.distance) == Some(e[0].distance))
+            .count();
+        let recall = hits as f32 / queries.len() as f32;
+        assert!(recall > 0.95, "recall@1 = {}", recall);
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut index = LshIndex::new(LshParams::default());
+        let descriptors: Vec<[u8; 32]> = (0..100).map(|_| rng.gen()).collect();
+        for descriptor in descriptors.iter() {
+            index.insert(*descriptor);
+        }
+
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.query(&descriptors[42], 1), vec![(42, 0)]);
+
+        assert!(index.remove(42));
+        assert!(!index.remove(42));
+        assert_eq!(index.len(), 99);
+        assert!(index.get(42).is_none());
+        assert!(index.query(&descriptors[42], 5).iter().all(|(id, _)| *id != 42));
+
+        // The slot is reused and the storage does not grow.
+        assert_eq!(index.insert(descriptors[7]), 42);
+        assert_eq!(index.descriptors.len(), 100);
+        assert_eq!(index.query(&descriptors[7], 2), vec![(7, 0), (42, 0)]);
+        assert_eq!(index.insert(descriptors[42]), 100);
+        assert_eq!(index.query(&descriptors[42], 1), vec![(100, 0)]);
+    }
+}
@@ -1,19 +1,127 @@
+use std::env;
+use std::process;
 
-pub mod image;
-pub mod descriptors;
-pub mod detectors;
-pub mod harris;
-pub mod lsd;
+use cv_rust::brisk::BriskExtractor;
+use cv_rust::detectors::{DescriptorExtractor, FastDetector, FeatureDetector, HarrisDetector, RbriefExtractor};
+use cv_rust::evaluation::{
+    evaluate, read_homography, synthetic_homographies, warp_perspective, EvaluationParams, Homography, CSV_HEADER,
+    PR_CSV_HEADER,
+};
+use cv_rust::image::GrayFloatImage;
+use cv_rust::matcher::Descriptor;
+use cv_rust::orb::OrbExtractor;
+use cv_rust::sift::SiftExtractor;
+
+const USAGE: &str = "usage: cv-rust evaluate [--detector orb|fast|harris] [--descriptor orb|rbrief|brisk|sift]
+                        [--max-distance PX] [--pr] IMAGE [IMAGE2 HOMOGRAPHY]
+
+Evaluates a detector and a descriptor on IMAGE against IMAGE2 related by the
+3x3 HOMOGRAPHY file, or against synthetic warps of IMAGE when no pair is given.
+The descriptor defaults to orb for the orb detector and to rbrief otherwise.
+Results are printed as CSV, the precision/recall curve instead with --pr.";
 
 fn main() {
-    
+    pretty_env_logger::init();
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("evaluate") => evaluate_command(&args[1..]),
+        _ => exit_with_usage(),
+    }
 }
 
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2)
+}
 
+fn evaluate_command(args: &[String]) {
+    let mut detector = "orb".to_string();
+    let mut descriptor = None;
+    let mut params = EvaluationParams::default();
+    let mut pr = false;
+    let mut paths = vec![];
 
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--detector" => detector = args.next().cloned().unwrap_or_else(|| exit_with_usage()),
+            "--descriptor" => descriptor = Some(args.next().cloned().unwrap_or_else(|| exit_with_usage())),
+            "--max-distance" => {
+                params.max_distance = args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--pr" => pr = true,
+            _ => paths.push(arg.as_str()),
+        }
+    }
 
+    let image = match paths.first() {
+        Some(path) => GrayFloatImage::load_image(path),
+        None => exit_with_usage(),
+    };
+    let pairs: Vec<(String, GrayFloatImage, Homography)> = match paths[1..] {
+        [] => {
+            let (width, height) = (image.width() as u32, image.height() as u32);
+            synthetic_homographies(width, height)
+                .into_iter()
+                .map(|(label, h)| {
+                    let warped = warp_perspective(&image, &h, width, height);
+                    (label, warped, h)
+                })
+                .collect()
+        }
+        [image2, homography] => {
+            let h = read_homography(homography).unwrap_or_else(|e| {
+                eprintln!("cannot read {}: {}", homography, e);
+                process::exit(1)
+            });
+            vec![(image2.to_string(), GrayFloatImage::load_image(image2), h)]
+        }
+        _ => exit_with_usage(),
+    };
 
+    let detector_is_orb = detector == "orb";
+    let detector: Box<dyn FeatureDetector> = match detector.as_str() {
+        "orb" => Box::new(OrbExtractor::default()),
+        "fast" => Box::new(FastDetector::default()),
+        "harris" => Box::new(HarrisDetector::default()),
+        _ => exit_with_usage(),
+    };
+    let descriptor = descriptor.unwrap_or_else(|| if detector_is_orb { "orb" } else { "rbrief" }.to_string());
 
+    match descriptor.as_str() {
+        "orb" => print_evaluations(&*detector, &OrbExtractor::default(), &image, &pairs, &params, pr),
+        "rbrief" => print_evaluations(&*detector, &RbriefExtractor::default(), &image, &pairs, &params, pr),
+        "brisk" => print_evaluations(&*detector, &BriskExtractor::default(), &image, &pairs, &params, pr),
+        "sift" => print_evaluations(&*detector, &SiftExtractor::default(), &image, &pairs, &params, pr),
+        _ => exit_with_usage(),
+    }
+}
 
-
+fn print_evaluations<F, E>(
+    detector: &F,
+    extractor: &E,
+    image: &GrayFloatImage,
+    pairs: &[(String, GrayFloatImage, Homography)],
+    params: &EvaluationParams,
+    pr: bool,
+) where
+    F: FeatureDetector + ?Sized,
+    E: DescriptorExtractor,
+    E::Descriptor: Descriptor,
+{
+    println!("{}", if pr { PR_CSV_HEADER } else { CSV_HEADER });
+    for (label, image2, h) in pairs {
+        let evaluation = evaluate(detector, extractor, image, image2, h, params);
+        if pr {
+            for row in evaluation.pr_csv_rows(label) {
+                println!("{}", row);
+            }
+        } else {
+            println!("{}", evaluation.csv_row(label));
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::KeyPoint;
+use crate::hamming::hamming_distance;
+
+/// Number of bins of the rotation consistency histogram.
+const HISTO_LENGTH: usize = 30;
+
+/// A correspondence between descriptor `query_idx` of the query set and
+/// descriptor `train_idx` of the train set.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Match {
+    pub query_idx: usize,
+    pub train_idx: usize,
+    pub distance: f32,
+}
+
+impl Match {
+    pub fn new(query_idx: usize, train_idx: usize, distance: f32) -> Match {
+        Match { query_idx, train_idx, distance }
+    }
+}
+
+/// A descriptor that can be compared with another one of the same kind.
+pub trait Descriptor: Sized {
+    fn distance(&self, other: &Self) -> f32;
+
+    /// Distances from `self` to every descriptor of `train`, in the same order.
+    fn distances(&self, train: &[Self], distances: &mut Vec<f32>) {
+        distances.clear();
+        distances.extend(train.iter().map(|t| self.distance(t)));
+    }
+}
+
+// The scalar kernel is used on purpose: `count_ones` compiles to `popcnt` or to
+// LLVM's own vectorised popcount and beats the `wide` kernels in `hamming`, see
+// `bench_hamming_scalar_vs_simd`.
+macro_rules! impl_binary_descriptor {
+    ($($len:literal),*) => {
+        $(
+            impl Descriptor for [u8; $len] {
+                fn distance(&self, other: &Self) -> f32 {
+                    hamming_distance(self, other) as f32
+                }
+            }
+        )*
+    };
+}
+
+impl_binary_descriptor!(32, 64);
+
+/// Euclidean distance between two float vectors of the same length.
+pub fn l2_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt()
+}
+
+/// `1 - cos(a, b)`, in `[0, 2]`; zero vectors are at distance 1 from anything.
+pub fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0f32, 0f32, 0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    let norm = (norm_a * norm_b).sqrt();
+    if norm > f32::EPSILON {
+        1.0 - dot / norm
+    } else {
+        1.0
+    }
+}
+
+/// Float descriptors such as [`crate::sift::SiftDescriptor`] are compared with the
+/// L2 distance.
+impl<const N: usize> Descriptor for [f32; N] {
+    fn distance(&self, other: &Self) -> f32 {
+        l2_distance(self, other)
+    }
+}
+
+/// Float descriptor compared with the cosine distance instead of L2, for
+/// descriptors that are not normalised.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Cosine<D>(pub D);
+
+impl<const N: usize> Descriptor for Cosine<[f32; N]> {
+    fn distance(&self, other: &Self) -> f32 {
+        cosine_distance(&self.0, &other.0)
+    }
+}
+
+/// The `k` nearest train descriptors of every query descriptor, closest first.
+pub fn knn_match<D: Descriptor>(query: &[D], train: &[D], k: usize) -> Vec<Vec<Match>> {
+    let mut distances = Vec::with_capacity(train.len());
+    query
+        .iter()
+        .enumerate()
+        .map(|(query_idx, q)| {
+            q.distances(train, &mut distances);
+            let mut nearest: Vec<Match> = Vec::with_capacity(k + 1);
+            for (train_idx, &distance) in distances.iter().enumerate() {
+                if nearest.len() == k && nearest.last().is_some_and(|m| distance >= m.distance) {
+                    continue;
+                }
+                let position = nearest.partition_point(|m| m.distance <= distance);
+                nearest.insert(position, Match::new(query_idx, train_idx, distance));
+                nearest.truncate(k);
+            }
+            nearest
+        })
+        .collect()
+}
+
+/// Best train descriptor for every query descriptor.
+pub fn match_descriptors<D: Descriptor>(query: &[D], train: &[D]) -> Vec<Match> {
+    knn_match(query, train, 1).into_iter().flatten().collect()
+}
+
+/// Lowe's ratio test: keeps the best match of a query when it is clearly better
+/// than the second best, i.e. `best < ratio * second`. Queries with a single
+/// candidate are kept.
+pub fn ratio_test(knn_matches: &[Vec<Match>], ratio: f32) -> Vec<Match> {
+    knn_matches
+        .iter()
+        .filter_map(|candidates| match candidates.as_slice() {
+            [best, second, ..] if best.distance < ratio * second.distance => Some(*best),
+            [best] => Some(*best),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Keeps the matches whose train descriptor also has the query descriptor as its
+/// best match.
+pub fn cross_check<D: Descriptor>(query: &[D], train: &[D], matches: &[Match]) -> Vec<Match> {
+    let reverse = match_descriptors(train, query);
+    matches
+        .iter()
+        .filter(|m| reverse[m.train_idx].train_idx == m.query_idx)
+        .copied()
+        .collect()
+}
+
+/// Rotation consistency check from ORB-SLAM: the angle differences of all matches
+/// are binned in a histogram and only matches in the three most voted bins are
+/// kept. The second and third bins are dropped if they get less than a tenth of
+/// the votes of the first one.
+///
+/// # Panics
+///
+/// If a match refers to a keypoint outside `query_keypoints` or `train_keypoints`.
+pub fn filter_by_rotation(matches: &[Match], query_keypoints: &[KeyPoint], train_keypoints: &[KeyPoint]) -> Vec<Match> {
+    for m in matches {
+        assert!(
+            m.query_idx < query_keypoints.len() && m.train_idx < train_keypoints.len(),
+            "match {:?} out of range of {} query and {} train keypoints",
+            m,
+            query_keypoints.len(),
+            train_keypoints.len()
+        );
+    }
+
+    let factor = HISTO_LENGTH as f32 / 360.0;
+    let bin_of = |m: &Match| {
+        let mut rotation = query_keypoints[m.query_idx].angle - train_keypoints[m.train_idx].angle;
+        if rotation < 0.0 {
+            rotation += 360.0;
+        }
+        (rotation * factor).round() as usize % HISTO_LENGTH
+    };
+
+    let mut histogram = [0usize; HISTO_LENGTH];
+    for m in matches {
+        histogram[bin_of(m)] += 1;
+    }
+
+    let kept = three_maxima(&histogram);
+    matches
+        .iter()
+        .filter(|m| kept.contains(&Some(bin_of(m))))
+        .copied()
+        .collect()
+}
+
+fn three_maxima(histogram: &[usize; HISTO_LENGTH]) -> [Option<usize>; 3] {
+    let mut order: Vec<usize> = (0..HISTO_LENGTH).collect();
+    order.sort_by_key(|&bin| std::cmp::Reverse(histogram[bin]));
+
+    let max1 = histogram[order[0]];
+    let pick = |bin: usize| {
+        let votes = histogram[bin];
+        (votes > 0 && votes as f32 >= 0.1 * max1 as f32).then_some(bin)
+    };
+
+    [pick(order[0]), pick(order[1]), pick(order[2])]
+}
+
+/// Brute-force matcher combining the filters above.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BruteForceMatcher {
+    /// Ratio for Lowe's test, disabled when `None`.
+    pub ratio: Option<f32>,
+    /// Matches with a larger distance are discarded.
+    pub max_distance: Option<f32>,
+    /// Keep only mutual best matches.
+    pub cross_check: bool,
+    /// Apply the rotation histogram check, requires keypoints.
+    pub check_orientation: bool,
+}
+
+impl Default for BruteForceMatcher {
+    fn default() -> Self {
+        BruteForceMatcher {
+            ratio: Some(0.75),
+            max_distance: None,
+            cross_check: true,
+            check_orientation: true,
+        }
+    }
+}
+
+impl BruteForceMatcher {
+    /// Matches `query` against `train`. Keypoints aligned with the descriptors are
+    /// only needed for the orientation check and can be empty otherwise.
+    pub fn match_features<D: Descriptor>(
+        &self,
+        query: &[D],
+        train: &[D],
+        query_keypoints: &[KeyPoint],
+        train_keypoints: &[KeyPoint],
+    ) -> Vec<Match> {
+        let mut matches = match self.ratio {
+            Some(ratio) => ratio_test(&knn_match(query, train, 2), ratio),
+            None => match_descriptors(query, train),
+        };
+
+        if let Some(max_distance) = self.max_distance {
+            matches.retain(|m| m.distance <= max_distance);
+        }
+
+        if self.cross_check {
+            matches = cross_check(query, train, &matches);
+        }
+
+        if self.check_orientation && !query_keypoints.is_empty() && !train_keypoints.is_empty() {
+            matches = filter_by_rotation(&matches, query_keypoints, train_keypoints);
+        }
+
+        matches
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{cross_check, filter_by_rotation, knn_match, ratio_test, BruteForceMatcher, Cosine, Descriptor, Match};
+    use crate::KeyPoint;
+
+    fn random_descriptors(rng: &mut StdRng, n: usize) -> Vec<[u8; 32]> {
+        (0..n).map(|_| rng.gen()).collect()
+    }
+
+    fn flip_bits(rng: &mut StdRng, descriptor: &[u8; 32], n: usize) -> [u8; 32] {
+        let mut flipped = *descriptor;
+        for _ in 0..n {
+            let bit = rng.gen_range(0..256);
+            flipped[bit / 8] ^= 1 << (bit % 8);
+        }
+        flipped
+    }
+
+    fn keypoint(angle: f32) -> KeyPoint {
+        KeyPoint { angle, ..KeyPoint::new(0.0, 0.0, 31.0) }
+    }
+
+    #[test]
+    fn knn_and_filters_recover_perturbed_descriptors() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let train = random_descriptors(&mut rng, 200);
+        let query: Vec<[u8; 32]> = train.iter().rev().map(|d| flip_bits(&mut rng, d, 10)).collect();
+
+        let knn = knn_match(&query, &train, 3);
+        for (query_idx, candidates) in knn.iter().enumerate() {
+            assert_eq!(candidates.len(), 3);
+            assert_eq!(candidates[0].train_idx, 199 - query_idx);
+            assert!(candidates.windows(2).all(|w| w[0].distance <= w[1].distance));
+        }
+
+        let matches = cross_check(&query, &train, &ratio_test(&knn, 0.8));
+        assert_eq!(matches.len(), 200);
+    }
+
+    #[test]
+    fn ratio_test_rejects_ambiguous_matches() {
+        let knn = vec![
+            vec![Match::new(0, 1, 10.0), Match::new(0, 2, 40.0)],
+            vec![Match::new(1, 3, 30.0), Match::new(1, 4, 32.0)],
+        ];
+        assert_eq!(ratio_test(&knn, 0.75), vec![Match::new(0, 1, 10.0)]);
+    }
+
+    #[test]
+    fn rotation_histogram_drops_inconsistent_matches() {
+        let query: Vec<KeyPoint> = (0..12).map(|i| keypoint(if i == 11 { 200.0 } else { 40.0 + i as f32 })).collect();
+        let train: Vec<KeyPoint> = (0..12).map(|_| keypoint(10.0)).collect();
+        let matches: Vec<Match> = (0..12).map(|i| Match::new(i, i, 0.0)).collect();
+
+        let kept = filter_by_rotation(&matches, &query, &train);
+        assert_eq!(kept.len(), 11);
+        assert!(kept.iter().all(|m| m.query_idx != 11));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn rotation_histogram_checks_keypoint_indices() {
+        let keypoints = vec![keypoint(10.0); 2];
+        filter_by_rotation(&[Match::new(0, 2, 0.0)], &keypoints, &keypoints);
+    }
+
+    #[test]
+    fn brute_force_matcher_without_keypoints() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let train = random_descriptors(&mut rng, 50);
+        let query: Vec<[u8; 32]> = train.iter().map(|d| flip_bits(&mut rng, d, 5)).collect();
+
+        let matcher = BruteForceMatcher { max_distance: Some(50.0), ..BruteForceMatcher::default() };
+        let matches = matcher.match_features(&query, &train, &[], &[]);
+        assert_eq!(matches.len(), 50);
+        assert!(matches.iter().all(|m| m.query_idx == m.train_idx));
+    }
+
+    #[test]
+    fn float_distances() {
+        let (a, b) = ([3.0f32, 0.0, 0.0], [0.0f32, 4.0, 0.0]);
+        assert_eq!(a.distance(&b), 5.0);
+        assert!((Cosine(a).distance(&Cosine(b)) - 1.0).abs() < 1e-6);
+        assert!(Cosine(a).distance(&Cosine([6.0, 0.0, 0.0])).abs() < 1e-6);
+        assert!((Cosine(a).distance(&Cosine([-1.0, 0.0, 0.0])) - 2.0).abs() < 1e-6);
+    }
+}
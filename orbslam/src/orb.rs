@@ -0,0 +1,445 @@
+use std::collections::HashSet;
+use std::time::Instant;
+
+use image::imageops;
+use log::info;
+
+use crate::descriptors::{
+    compute_orientation, compute_pyramid_descriptors, fast_non_max_suppression, float_corners_fast9,
+    OrbDescriptor, PATCH_SIZE,
+};
+use crate::image::{build_pyramid, GrayFloatImage};
+use crate::KeyPoint;
+
+/// Keypoints closer than this to a level's border are not detected, so that the
+/// orientation patch and the BRIEF pattern stay inside the image.
+const EDGE_THRESHOLD: u32 = 19;
+
+/// Approximate side of the grid cells FAST runs on.
+const CELL_SIZE: f32 = 30.0;
+
+/// Tunables of the extractor, named after the `ORBextractor.*` entries of
+/// ORB-SLAM's settings file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OrbSettings {
+    /// Number of features to keep over all levels (`nFeatures`).
+    pub n_features: usize,
+    /// Scale between consecutive pyramid levels (`scaleFactor`).
+    pub scale_factor: f32,
+    /// Number of pyramid levels (`nLevels`).
+    pub n_levels: usize,
+    /// FAST threshold tried first in every cell (`iniThFAST`).
+    pub ini_th_fast: u8,
+    /// FAST threshold used for cells where `ini_th_fast` finds nothing (`minThFAST`).
+    pub min_th_fast: u8,
+}
+
+impl Default for OrbSettings {
+    fn default() -> Self {
+        OrbSettings {
+            n_features: 1000,
+            scale_factor: 1.2,
+            n_levels: 8,
+            ini_th_fast: 20,
+            min_th_fast: 7,
+        }
+    }
+}
+
+/// ORB feature extractor following ORB-SLAM's `ORBextractor`: FAST per grid cell
+/// on every pyramid level, quadtree distribution of the features, intensity
+/// centroid orientation and rBRIEF descriptors.
+pub struct OrbExtractor {
+    settings: OrbSettings,
+    scale_factors: Vec<f32>,
+    level_sigma2: Vec<f32>,
+    features_per_level: Vec<usize>,
+}
+
+impl OrbExtractor {
+    /// # Panics
+    ///
+    /// If `scale_factor` is not larger than 1 while there are several levels.
+    pub fn new(settings: OrbSettings) -> Self {
+        let n_levels = settings.n_levels.max(1);
+        assert!(
+            n_levels == 1 || settings.scale_factor > 1.0,
+            "scale factor must be larger than 1, got {}",
+            settings.scale_factor
+        );
+
+        let mut scale_factors = vec![1f32; n_levels];
+        for level in 1..n_levels {
+            scale_factors[level] = scale_factors[level - 1] * settings.scale_factor;
+        }
+        let level_sigma2 = scale_factors.iter().map(|s| s * s).collect();
+
+        // Geometric distribution of the features over the levels, so that every
+        // level gets a share proportional to its area.
+        let factor = 1.0 / settings.scale_factor;
+        let mut desired = settings.n_features as f32 * (1.0 - factor) / (1.0 - factor.powi(n_levels as i32));
+        let mut features_per_level = vec![0usize; n_levels];
+        let mut sum_features = 0;
+        for count in features_per_level.iter_mut().take(n_levels - 1) {
+            *count = desired.round() as usize;
+            sum_features += *count;
+            desired *= factor;
+        }
+        features_per_level[n_levels - 1] = settings.n_features.saturating_sub(sum_features);
+
+        OrbExtractor {
+            settings: OrbSettings { n_levels, ..settings },
+            scale_factors,
+            level_sigma2,
+            features_per_level,
+        }
+    }
+
+    pub fn settings(&self) -> &OrbSettings {
+        &self.settings
+    }
+
+    /// Scale of every level relative to level 0.
+    pub fn scale_factors(&self) -> &[f32] {
+        &self.scale_factors
+    }
+
+    /// Squared scale of every level, used to weight reprojection errors.
+    pub fn level_sigma2(&self) -> &[f32] {
+        &self.level_sigma2
+    }
+
+    pub fn features_per_level(&self) -> &[usize] {
+        &self.features_per_level
+    }
+
+    pub fn compute_pyramid(&self, image: &GrayFloatImage) -> Vec<GrayFloatImage> {
+        build_pyramid(image, self.settings.n_levels, self.settings.scale_factor)
+    }
+
+    /// Extracts ORB features from `image`. Keypoint coordinates are rescaled to
+    /// level 0 and `octave` records the level they were found on; descriptors are
+    /// aligned with the keypoints.
+    pub fn extract(&self, image: &GrayFloatImage) -> (Vec<KeyPoint>, Vec<OrbDescriptor>) {
+        let start = Instant::now();
+        let pyramid = self.compute_pyramid(image);
+        let keypoints = self.detect_pyramid(&pyramid);
+        let descriptors = self.describe_pyramid(&pyramid, &keypoints);
+
+        info!("Extracted {} ORB features in : {:?}", keypoints.len(), start.elapsed());
+        (keypoints, descriptors)
+    }
+
+    /// Oriented keypoints of [`OrbExtractor::extract`] without their descriptors.
+    pub fn detect(&self, image: &GrayFloatImage) -> Vec<KeyPoint> {
+        self.detect_pyramid(&self.compute_pyramid(image))
+    }
+
+    /// Descriptors of keypoints given in level 0 coordinates, each one sampled on
+    /// the pyramid level of its `octave`. Keypoints from other detectors or
+    /// settings may have an `octave` beyond this pyramid; they are described on
+    /// its coarsest level so that the output stays aligned with `keypoints`.
+    pub fn describe(&self, image: &GrayFloatImage, keypoints: &[KeyPoint]) -> Vec<OrbDescriptor> {
+        self.describe_pyramid(&self.compute_pyramid(image), keypoints)
+    }
+
+    fn detect_pyramid(&self, pyramid: &[GrayFloatImage]) -> Vec<KeyPoint> {
+        let mut keypoints = Vec::with_capacity(self.settings.n_features);
+
+        for (level, level_image) in pyramid.iter().enumerate() {
+            let mut level_keypoints = self.detect_level(level_image, level);
+            compute_orientation(level_image, &mut level_keypoints);
+
+            keypoints.extend(level_keypoints.iter().map(|k| k.to_level_zero(self.settings.scale_factor)));
+        }
+        keypoints
+    }
+
+    fn describe_pyramid(&self, pyramid: &[GrayFloatImage], keypoints: &[KeyPoint]) -> Vec<OrbDescriptor> {
+        let coarsest = pyramid.len() - 1;
+        let level_keypoints: Vec<KeyPoint> = keypoints
+            .iter()
+            .map(|keypoint| {
+                let keypoint = KeyPoint { octave: keypoint.octave.min(coarsest), ..*keypoint };
+                keypoint.to_octave(self.settings.scale_factor)
+            })
+            .collect();
+        compute_pyramid_descriptors(pyramid, &level_keypoints)
+    }
+
+    /// FAST on a grid of cells with a per-cell threshold fallback, then quadtree
+    /// distribution down to this level's feature budget. Coordinates are in the
+    /// level's frame.
+    fn detect_level(&self, image: &GrayFloatImage, level: usize) -> Vec<KeyPoint> {
+        let (width, height) = (image.width() as u32, image.height() as u32);
+        if width <= 2 * EDGE_THRESHOLD || height <= 2 * EDGE_THRESHOLD {
+            return vec![];
+        }
+
+        let min_border = EDGE_THRESHOLD - 3;
+        let (max_border_x, max_border_y) = (width - EDGE_THRESHOLD + 3, height - EDGE_THRESHOLD + 3);
+        let (region_width, region_height) = ((max_border_x - min_border) as f32, (max_border_y - min_border) as f32);
+
+        let n_cols = ((region_width / CELL_SIZE) as u32).max(1);
+        let n_rows = ((region_height / CELL_SIZE) as u32).max(1);
+        let cell_width = (region_width / n_cols as f32).ceil() as u32;
+        let cell_height = (region_height / n_rows as f32).ceil() as u32;
+
+        let mut seen = HashSet::new();
+        let mut candidates = vec![];
+
+        for row in 0..n_rows {
+            let ini_y = min_border + row * cell_height;
+            if ini_y + 3 >= max_border_y {
+                continue;
+            }
+            let max_y = (ini_y + cell_height + 6).min(max_border_y);
+
+            for col in 0..n_cols {
+                let ini_x = min_border + col * cell_width;
+                if ini_x + 6 >= max_border_x {
+                    continue;
+                }
+                let max_x = (ini_x + cell_width + 6).min(max_border_x);
+
+                let cell = GrayFloatImage(
+                    imageops::crop_imm(&image.0, ini_x, ini_y, max_x - ini_x, max_y - ini_y).to_image(),
+                );
+                let mut corners = float_corners_fast9(&cell, self.settings.ini_th_fast);
+                if corners.is_empty() {
+                    corners = float_corners_fast9(&cell, self.settings.min_th_fast);
+                }
+
+                // Cells overlap by six pixels, keep the first detection of a pixel.
+                for corner in fast_non_max_suppression(&corners, cell.width() as u32, cell.height() as u32) {
+                    let (x, y) = (corner.x + ini_x, corner.y + ini_y);
+                    if seen.insert((x, y)) {
+                        candidates.push(KeyPoint {
+                            x: x as f32,
+                            y: y as f32,
+                            ..KeyPoint::from_corner(&corner, level)
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut keypoints = distribute_quadtree(
+            candidates,
+            (min_border as f32, min_border as f32),
+            (max_border_x as f32, max_border_y as f32),
+            self.features_per_level[level],
+        );
+
+        let size = PATCH_SIZE as f32 * self.scale_factors[level];
+        for keypoint in keypoints.iter_mut() {
+            keypoint.size = size;
+        }
+        keypoints
+    }
+}
+
+impl Default for OrbExtractor {
+    fn default() -> Self {
+        OrbExtractor::new(OrbSettings::default())
+    }
+}
+
+/// A quadtree node: an axis aligned box and the keypoints inside it.
+struct Node {
+    min: (f32, f32),
+    max: (f32, f32),
+    keypoints: Vec<KeyPoint>,
+}
+
+impl Node {
+    /// Splits the node in four, dropping empty children.
+    fn divide(self) -> Vec<Node> {
+        let half_x = ((self.max.0 - self.min.0) / 2.0).ceil();
+        let half_y = ((self.max.1 - self.min.1) / 2.0).ceil();
+        let (mid_x, mid_y) = (self.min.0 + half_x, self.min.1 + half_y);
+
+        let mut children = [
+            Node { min: self.min, max: (mid_x, mid_y), keypoints: vec![] },
+            Node { min: (mid_x, self.min.1), max: (self.max.0, mid_y), keypoints: vec![] },
+            Node { min: (self.min.0, mid_y), max: (mid_x, self.max.1), keypoints: vec![] },
+            Node { min: (mid_x, mid_y), max: self.max, keypoints: vec![] },
+        ];
+
+        for keypoint in self.keypoints {
+            let index = match (keypoint.x < mid_x, keypoint.y < mid_y) {
+                (true, true) => 0,
+                (false, true) => 1,
+                (true, false) => 2,
+                (false, false) => 3,
+            };
+            children[index].keypoints.push(keypoint);
+        }
+
+        children.into_iter().filter(|child| !child.keypoints.is_empty()).collect()
+    }
+}
+
+/// Spreads keypoints over the region by recursively splitting it into quadrants
+/// until there are about `n_features` non-empty nodes, then keeps the keypoint with
+/// the highest response in every node (ORB-SLAM's `DistributeOctTree`).
+fn distribute_quadtree(
+    keypoints: Vec<KeyPoint>,
+    min: (f32, f32),
+    max: (f32, f32),
+    n_features: usize,
+) -> Vec<KeyPoint> {
+    if keypoints.is_empty() || n_features == 0 {
+        return vec![];
+    }
+
+    // Initial nodes are roughly square.
+    let (width, height) = (max.0 - min.0, max.1 - min.1);
+    let n_ini = ((width / height).round() as usize).max(1);
+    let step = width / n_ini as f32;
+
+    let mut nodes: Vec<Node> = (0..n_ini)
+        .map(|i| Node {
+            min: (min.0 + step * i as f32, min.1),
+            max: (min.0 + step * (i + 1) as f32, max.1),
+            keypoints: vec![],
+        })
+        .collect();
+    for keypoint in keypoints {
+        let index = (((keypoint.x - min.0) / step) as usize).min(n_ini - 1);
+        nodes[index].keypoints.push(keypoint);
+    }
+    nodes.retain(|node| !node.keypoints.is_empty());
+
+    loop {
+        let prev_size = nodes.len();
+        let mut n_to_expand = 0;
+        let mut next = Vec::with_capacity(nodes.len() * 4);
+
+        for node in nodes {
+            if node.keypoints.len() == 1 {
+                next.push(node);
+                continue;
+            }
+            for child in node.divide() {
+                if child.keypoints.len() > 1 {
+                    n_to_expand += 1;
+                }
+                next.push(child);
+            }
+        }
+        nodes = next;
+
+        if nodes.len() >= n_features || nodes.len() == prev_size {
+            break;
+        }
+
+        // One more full round would overshoot, so split the most populated nodes
+        // one at a time until the budget is reached.
+        if nodes.len() + n_to_expand * 3 > n_features {
+            loop {
+                let prev_size = nodes.len();
+
+                let mut order: Vec<usize> = (0..nodes.len()).filter(|&i| nodes[i].keypoints.len() > 1).collect();
+                order.sort_by_key(|&i| std::cmp::Reverse(nodes[i].keypoints.len()));
+
+                let mut count = nodes.len();
+                let mut split = vec![false; nodes.len()];
+                for &i in order.iter() {
+                    split[i] = true;
+                    count += 3;
+                    if count >= n_features {
+                        break;
+                    }
+                }
+
+                let mut next = Vec::with_capacity(count);
+                for (node, split) in nodes.into_iter().zip(split) {
+                    if split {
+                        next.extend(node.divide());
+                    } else {
+                        next.push(node);
+                    }
+                }
+                nodes = next;
+
+                if nodes.len() >= n_features || nodes.len() == prev_size {
+                    break;
+                }
+            }
+            break;
+        }
+    }
+
+    nodes
+        .into_iter()
+        .filter_map(|node| {
+            node.keypoints
+                .into_iter()
+                .max_by(|a, b| a.response.total_cmp(&b.response))
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{OrbExtractor, OrbSettings};
+    use crate::image::GrayFloatImage;
+    use crate::KeyPoint;
+
+    #[test]
+    fn extract_orb_features() {
+        let image = GrayFloatImage::load_image("input-image/test1.png");
+        let extractor = OrbExtractor::new(OrbSettings { n_features: 500, ..OrbSettings::default() });
+        let budget = extractor.features_per_level();
+        assert_eq!(budget.iter().sum::<usize>(), 500);
+
+        let (keypoints, descriptors) = extractor.extract(&image);
+        assert_eq!(keypoints.len(), descriptors.len());
+
+        let pyramid = extractor.compute_pyramid(&image);
+        let mut per_level = vec![0; pyramid.len()];
+        for keypoint in keypoints.iter() {
+            assert!(keypoint.octave < pyramid.len(), "{:?}", keypoint);
+            assert!((0.0..360.0).contains(&keypoint.angle));
+            // Level 0 coordinates, which fall inside the level the keypoint was found on.
+            assert!(keypoint.x >= 0.0 && keypoint.x < image.width() as f32, "{:?}", keypoint);
+            assert!(keypoint.y >= 0.0 && keypoint.y < image.height() as f32, "{:?}", keypoint);
+            let level = &pyramid[keypoint.octave];
+            let local = keypoint.to_octave(extractor.settings().scale_factor);
+            assert!(local.x < level.width() as f32 && local.y < level.height() as f32, "{:?}", keypoint);
+            per_level[keypoint.octave] += 1;
+        }
+
+        // Close to the requested number, spread over every level roughly as budgeted.
+        assert!(keypoints.len() >= 400 && keypoints.len() <= 550, "{} keypoints", keypoints.len());
+        for (&count, &wanted) in per_level.iter().zip(budget) {
+            assert!(count >= wanted / 2 && count <= wanted + wanted / 10 + 1, "{:?} for {:?}", per_level, budget);
+        }
+    }
+
+    #[test]
+    fn octaves_beyond_the_pyramid_use_the_coarsest_level() {
+        let image = GrayFloatImage::load_image("input-image/test1.png");
+        let extractor = OrbExtractor::new(OrbSettings { n_levels: 3, ..OrbSettings::default() });
+        let keypoint = KeyPoint { octave: 2, ..KeyPoint::new(200.0, 150.0, 31.0) };
+        let beyond = KeyPoint { octave: 7, ..keypoint };
+
+        let descriptors = extractor.describe(&image, &[keypoint, beyond]);
+        assert_eq!(descriptors.len(), 2);
+        assert_eq!(descriptors[0], descriptors[1]);
+    }
+
+    #[test]
+    fn single_level_budget() {
+        let extractor = OrbExtractor::new(OrbSettings { n_levels: 1, scale_factor: 1.0, ..OrbSettings::default() });
+        assert_eq!(extractor.features_per_level(), &[1000]);
+    }
+
+    #[test]
+    #[should_panic(expected = "scale factor")]
+    fn rejects_unit_scale_factor() {
+        OrbExtractor::new(OrbSettings { scale_factor: 1.0, ..OrbSettings::default() });
+    }
+}
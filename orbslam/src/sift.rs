@@ -0,0 +1,243 @@
+use ndarray::{Array2, Zip};
+
+use crate::detectors::DescriptorExtractor;
+use crate::image::{sobel_filter_x, sobel_filter_y, GrayFloatImage};
+use crate::KeyPoint;
+
+/// 4x4 spatial cells of 8 orientation bins, L2 normalised.
+pub type SiftDescriptor = [f32; 128];
+
+const CELLS: usize = 4;
+const ORIENTATION_BINS: usize = 8;
+const HISTOGRAM_BINS: usize = 36;
+/// Entries are clamped to this value after a first normalisation, which limits
+/// the influence of large gradients caused by non-linear illumination changes.
+const MAGNITUDE_CLAMP: f32 = 0.2;
+
+/// Gradient magnitude and orientation (degrees in `[0, 360)`) of every pixel,
+/// indexed `[[y, x]]`, from the Sobel filters of [`crate::image`].
+pub struct Gradients {
+    pub magnitude: Array2<f32>,
+    pub orientation: Array2<f32>,
+}
+
+impl Gradients {
+    pub fn new(image: &GrayFloatImage) -> Gradients {
+        let (i_x, i_y) = (sobel_filter_x(image), sobel_filter_y(image));
+        let magnitude = Zip::from(&i_x).and(&i_y).map_collect(|gx, gy| gx.hypot(*gy));
+        let orientation = Zip::from(&i_x).and(&i_y).map_collect(|gx, gy| {
+            let angle = gy.atan2(*gx).to_degrees();
+            if angle < 0.0 {
+                angle + 360.0
+            } else {
+                angle
+            }
+        });
+        Gradients { magnitude, orientation }
+    }
+
+    fn at(&self, x: i32, y: i32) -> Option<(f32, f32)> {
+        let (height, width) = self.magnitude.dim();
+        if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+            return None;
+        }
+        let index = [y as usize, x as usize];
+        Some((self.magnitude[index], self.orientation[index]))
+    }
+}
+
+/// Dominant gradient orientation around the keypoint in degrees, from a
+/// Gaussian weighted 36-bin histogram over a disc of diameter `size`, refined by
+/// a parabola through the highest bin and its neighbours.
+pub fn dominant_orientation(gradients: &Gradients, keypoint: &KeyPoint) -> f32 {
+    let radius = (keypoint.size / 2.0).max(1.0);
+    let sigma = radius / 2.0;
+    let (cx, cy) = (keypoint.x.round() as i32, keypoint.y.round() as i32);
+    let r = radius.ceil() as i32;
+
+    let mut histogram = [0f32; HISTOGRAM_BINS];
+    for dy in -r..=r {
+        for dx in -r..=r {
+            let d2 = (dx * dx + dy * dy) as f32;
+            if d2 > radius * radius {
+                continue;
+            }
+            if let Some((magnitude, orientation)) = gradients.at(cx + dx, cy + dy) {
+                let bin = (orientation * HISTOGRAM_BINS as f32 / 360.0).round() as usize % HISTOGRAM_BINS;
+                histogram[bin] += magnitude * (-d2 / (2.0 * sigma * sigma)).exp();
+            }
+        }
+    }
+
+    // Smooth the histogram with a [1, 4, 6, 4, 1] / 16 kernel.
+    let smoothed: Vec<f32> = (0..HISTOGRAM_BINS)
+        .map(|i| {
+            let at = |offset: isize| histogram[(i as isize + offset).rem_euclid(HISTOGRAM_BINS as isize) as usize];
+            (at(-2) + at(2) + 4.0 * (at(-1) + at(1)) + 6.0 * at(0)) / 16.0
+        })
+        .collect();
+
+    let best = (0..HISTOGRAM_BINS).max_by(|a, b| smoothed[*a].total_cmp(&smoothed[*b])).unwrap();
+    let (left, center, right) = (
+        smoothed[(best + HISTOGRAM_BINS - 1) % HISTOGRAM_BINS],
+        smoothed[best],
+        smoothed[(best + 1) % HISTOGRAM_BINS],
+    );
+    let denominator = left - 2.0 * center + right;
+    let offset = if denominator.abs() > f32::EPSILON { 0.5 * (left - right) / denominator } else { 0.0 };
+
+    (best as f32 + offset).rem_euclid(HISTOGRAM_BINS as f32) * 360.0 / HISTOGRAM_BINS as f32
+}
+
+/// SIFT descriptor (Lowe, "Distinctive image features from scale-invariant
+/// keypoints") over a square of side `keypoint.size` rotated by `angle` degrees.
+/// Gradients are spread over neighbouring cells and orientation bins by trilinear
+/// interpolation and weighted by a Gaussian of half the window width.
+pub fn compute_sift_descriptor(gradients: &Gradients, keypoint: &KeyPoint, angle: f32) -> SiftDescriptor {
+    let cell = (keypoint.size / CELLS as f32).max(1.0);
+    let half = cell * CELLS as f32 / 2.0;
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (cx, cy) = (keypoint.x.round() as i32, keypoint.y.round() as i32);
+    // The rotated window fits in a square of this radius.
+    let r = (half * 2f32.sqrt() + cell).ceil() as i32;
+    let sigma = half;
+
+    let mut histogram = [0f32; 128];
+    for dy in -r..=r {
+        for dx in -r..=r {
+            let Some((magnitude, orientation)) = gradients.at(cx + dx, cy + dy) else {
+                continue;
+            };
+
+            // Offset in the keypoint frame, in cells, with cell centers at integers.
+            let (ox, oy) = (dx as f32 + cx as f32 - keypoint.x, dy as f32 + cy as f32 - keypoint.y);
+            let (rx, ry) = (cos * ox + sin * oy, -sin * ox + cos * oy);
+            let (bx, by) = (rx / cell + CELLS as f32 / 2.0 - 0.5, ry / cell + CELLS as f32 / 2.0 - 0.5);
+            if bx <= -1.0 || by <= -1.0 || bx >= CELLS as f32 || by >= CELLS as f32 {
+                continue;
+            }
+
+            let weight = magnitude * (-(rx * rx + ry * ry) / (2.0 * sigma * sigma)).exp();
+            let bo = (orientation - angle).rem_euclid(360.0) * ORIENTATION_BINS as f32 / 360.0;
+            add_trilinear(&mut histogram, bx, by, bo, weight);
+        }
+    }
+
+    normalize(&mut histogram);
+    for value in histogram.iter_mut() {
+        *value = value.min(MAGNITUDE_CLAMP);
+    }
+    normalize(&mut histogram);
+    histogram
+}
+
+fn add_trilinear(histogram: &mut [f32; 128], bx: f32, by: f32, bo: f32, weight: f32) {
+    let (x0, y0, o0) = (bx.floor(), by.floor(), bo.floor());
+    let (fx, fy, fo) = (bx - x0, by - y0, bo - o0);
+
+    for (ix, wx) in [(x0 as i32, 1.0 - fx), (x0 as i32 + 1, fx)] {
+        if ix < 0 || ix >= CELLS as i32 {
+            continue;
+        }
+        for (iy, wy) in [(y0 as i32, 1.0 - fy), (y0 as i32 + 1, fy)] {
+            if iy < 0 || iy >= CELLS as i32 {
+                continue;
+            }
+            for (io, wo) in [(o0 as usize, 1.0 - fo), (o0 as usize + 1, fo)] {
+                let index = (iy as usize * CELLS + ix as usize) * ORIENTATION_BINS + io % ORIENTATION_BINS;
+                histogram[index] += weight * wx * wy * wo;
+            }
+        }
+    }
+}
+
+fn normalize(values: &mut [f32]) {
+    let norm = values.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > f32::EPSILON {
+        for value in values.iter_mut() {
+            *value /= norm;
+        }
+    }
+}
+
+/// SIFT-like float descriptors behind the common extractor interface, meant for
+/// offline map building where matching quality matters more than speed.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SiftExtractor {
+    /// Use the keypoint `angle` instead of the dominant gradient orientation.
+    pub use_keypoint_orientation: bool,
+}
+
+impl DescriptorExtractor for SiftExtractor {
+    type Descriptor = SiftDescriptor;
+
+    fn compute(&self, image: &GrayFloatImage, keypoints: &[KeyPoint]) -> Vec<SiftDescriptor> {
+        let gradients = Gradients::new(image);
+        keypoints
+            .iter()
+            .map(|keypoint| {
+                let angle = if self.use_keypoint_orientation {
+                    keypoint.angle
+                } else {
+                    dominant_orientation(&gradients, keypoint)
+                };
+                compute_sift_descriptor(&gradients, keypoint, angle)
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{dominant_orientation, Gradients, SiftExtractor};
+    use crate::detectors::DescriptorExtractor;
+    use crate::evaluation::{rotation_about, warp_perspective};
+    use crate::image::{gaussian_blur, noise_image, GrayFloatImage};
+    use crate::matcher::Descriptor;
+    use crate::KeyPoint;
+
+    /// Intensity ramp whose gradient points at `angle` degrees.
+    fn ramp(size: u32, angle: f32) -> GrayFloatImage {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let mut image = GrayFloatImage::new(size, size);
+        for y in 0..size as usize {
+            for x in 0..size as usize {
+                image.put(x, y, (x as f32 * cos + y as f32 * sin) / (2.0 * size as f32) + 0.5);
+            }
+        }
+        image
+    }
+
+    fn texture(size: u32) -> GrayFloatImage {
+        gaussian_blur(&noise_image(size, 777), 2.0)
+    }
+
+    #[test]
+    fn orientation_follows_the_gradient() {
+        for angle in [0.0, 30.0, 135.0, 250.0] {
+            let image = ramp(41, angle);
+            let estimated = dominant_orientation(&Gradients::new(&image), &KeyPoint::new(20.0, 20.0, 20.0));
+            let error = (estimated - angle + 180.0).rem_euclid(360.0) - 180.0;
+            assert!(error.abs() < 5.0, "expected {} got {}", angle, estimated);
+        }
+    }
+
+    #[test]
+    fn descriptor_is_normalised_and_rotation_invariant() {
+        let size = 129;
+        let image = texture(size);
+        let center = size as f32 / 2.0 - 0.5;
+        let rotated = warp_perspective(&image, &rotation_about(60.0, center, center), size, size);
+
+        let extractor = SiftExtractor::default();
+        let keypoint = KeyPoint::new(64.0, 64.0, 31.0);
+        let a = extractor.compute(&image, &[keypoint])[0];
+        let b = extractor.compute(&rotated, &[keypoint])[0];
+        let other = extractor.compute(&image, &[KeyPoint::new(90.0, 40.0, 31.0)])[0];
+
+        assert!((a.iter().map(|v| v * v).sum::<f32>() - 1.0).abs() < 1e-4);
+        let (same, different) = (a.distance(&b), a.distance(&other));
+        assert!(same < 0.5 * different, "rotated {} other {}", same, different);
+    }
+}
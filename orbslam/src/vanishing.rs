@@ -0,0 +1,557 @@
+use rand::{rngs::StdRng, seq::index, Rng, SeedableRng};
+
+use crate::line::LineSegment;
+
+type Vec3 = [f64; 3];
+
+/// Pinhole camera intrinsics, in pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Intrinsics {
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+}
+
+impl Intrinsics {
+    /// Stand-in calibration for uncalibrated images: principal point at the
+    /// image center and a focal length of the largest image side, which keeps the
+    /// computations on the Gaussian sphere well conditioned.
+    pub fn uncalibrated(width: usize, height: usize) -> Intrinsics {
+        let focal = width.max(height).max(1) as f32;
+        Intrinsics {
+            fx: focal,
+            fy: focal,
+            cx: (width as f32 - 1.0) / 2.0,
+            cy: (height as f32 - 1.0) / 2.0,
+        }
+    }
+
+    /// Viewing ray of the pixel `(x, y)`, not normalised.
+    fn backproject(&self, x: f32, y: f32) -> Vec3 {
+        [((x - self.cx) / self.fx) as f64, ((y - self.cy) / self.fy) as f64, 1.0]
+    }
+
+    /// Homogeneous image of the direction `d`.
+    fn project(&self, d: &Vec3) -> [f32; 3] {
+        let [x, y, z] = d.map(|v| v as f32);
+        [self.fx * x + self.cx * z, self.fy * y + self.cy * z, z]
+    }
+}
+
+/// Parameters of [`detect_vanishing_points`] and
+/// [`detect_manhattan_vanishing_points`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VanishingPointParams {
+    /// Maximal angle, in degrees, between a segment and the line joining its
+    /// midpoint to a vanishing point for the segment to belong to it.
+    pub max_angle: f32,
+    /// Shorter segments are assigned to vanishing points but do not vote, their
+    /// direction being too uncertain.
+    pub min_length: f32,
+    pub iterations: usize,
+    /// Maximal number of vanishing points of [`detect_vanishing_points`].
+    pub max_points: usize,
+    /// Minimal number of voting segments of a vanishing point.
+    pub min_support: usize,
+    pub seed: u64,
+}
+
+impl Default for VanishingPointParams {
+    fn default() -> Self {
+        VanishingPointParams {
+            max_angle: 2.0,
+            min_length: 20.0,
+            iterations: 500,
+            max_points: 3,
+            min_support: 5,
+            seed: 0,
+        }
+    }
+}
+
+/// Vanishing points of a set of segments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VanishingPoints {
+    /// Unit directions in the camera frame, up to sign. For uncalibrated images
+    /// they refer to [`Intrinsics::uncalibrated`].
+    pub directions: Vec<[f32; 3]>,
+    /// Homogeneous image points of the directions, with a zero last coordinate
+    /// for points at infinity.
+    pub points: Vec<[f32; 3]>,
+    /// Vanishing point of each segment, aligned with the segments; `None` for
+    /// segments consistent with none of them.
+    pub assignment: Vec<Option<usize>>,
+}
+
+impl VanishingPoints {
+    /// Image coordinates of vanishing point `i`, `None` when it is at infinity.
+    pub fn point(&self, i: usize) -> Option<(f32, f32)> {
+        let [x, y, w] = self.points[i];
+        (w.abs() > f32::EPSILON).then(|| (x / w, y / w))
+    }
+
+    /// Indices of the segments assigned to vanishing point `i`.
+    pub fn cluster(&self, i: usize) -> Vec<usize> {
+        (0..self.assignment.len()).filter(|&s| self.assignment[s] == Some(i)).collect()
+    }
+}
+
+/// Segment seen through the camera: its interpretation plane, spanned by the
+/// viewing rays of its endpoints, and its weight.
+struct Observation {
+    normal: Vec3,
+    weight: f64,
+}
+
+fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: &Vec3, b: &Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalized(v: Vec3) -> Option<Vec3> {
+    let norm = dot(&v, &v).sqrt();
+    (norm > 1e-12).then(|| v.map(|x| x / norm))
+}
+
+/// Angle in degrees, in `[0, 90]`, between `segment` and the line joining its
+/// midpoint to the image `vp` of a direction.
+fn angle_to(segment: &LineSegment, vp: &[f32; 3]) -> f32 {
+    let (mx, my) = segment.midpoint();
+    let (dx, dy) = segment.direction();
+    let (vx, vy) = (vp[0] - mx * vp[2], vp[1] - my * vp[2]);
+    let norm = vx.hypot(vy);
+    if norm <= f32::EPSILON {
+        return 0.0;
+    }
+    let cos = ((dx * vx + dy * vy) / norm).abs().min(1.0);
+    cos.acos().to_degrees()
+}
+
+/// Unit eigenvector of the smallest eigenvalue of the symmetric matrix `m`, by
+/// cyclic Jacobi rotations.
+fn smallest_eigenvector(mut m: [[f64; 3]; 3]) -> Vec3 {
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..50 {
+        let off = m[0][1] * m[0][1] + m[0][2] * m[0][2] + m[1][2] * m[1][2];
+        if off < 1e-24 {
+            break;
+        }
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if m[p][q].abs() < 1e-300 {
+                continue;
+            }
+            let theta = (m[q][q] - m[p][p]) / (2.0 * m[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+            for row in m.iter_mut() {
+                let (mp, mq) = (row[p], row[q]);
+                row[p] = c * mp - s * mq;
+                row[q] = s * mp + c * mq;
+            }
+            let (mp, mq) = (m[p], m[q]);
+            m[p] = std::array::from_fn(|k| c * mp[k] - s * mq[k]);
+            m[q] = std::array::from_fn(|k| s * mp[k] + c * mq[k]);
+            for row in v.iter_mut() {
+                let (vp, vq) = (row[p], row[q]);
+                row[p] = c * vp - s * vq;
+                row[q] = s * vp + c * vq;
+            }
+        }
+    }
+    let smallest = (0..3).min_by(|a, b| m[*a][*a].total_cmp(&m[*b][*b])).unwrap();
+    [v[0][smallest], v[1][smallest], v[2][smallest]]
+}
+
+/// Least squares direction lying in all the interpretation planes of `members`.
+fn refine(observations: &[Observation], members: &[usize]) -> Option<Vec3> {
+    let mut m = [[0f64; 3]; 3];
+    for &i in members {
+        let Observation { normal: n, weight } = &observations[i];
+        for r in 0..3 {
+            for c in 0..3 {
+                m[r][c] += weight * n[r] * n[c];
+            }
+        }
+    }
+    normalized(smallest_eigenvector(m))
+}
+
+struct Detector<'a> {
+    segments: &'a [LineSegment],
+    intrinsics: Intrinsics,
+    params: &'a VanishingPointParams,
+    observations: Vec<Observation>,
+    /// Segments long enough to vote.
+    voters: Vec<usize>,
+}
+
+impl<'a> Detector<'a> {
+    fn new(segments: &'a [LineSegment], intrinsics: Intrinsics, params: &'a VanishingPointParams) -> Self {
+        let observations: Vec<Observation> = segments
+            .iter()
+            .map(|s| {
+                let normal = cross(&intrinsics.backproject(s.x1, s.y1), &intrinsics.backproject(s.x2, s.y2));
+                Observation { normal: normalized(normal).unwrap_or([0.0; 3]), weight: s.length() as f64 }
+            })
+            .collect();
+        let voters = (0..segments.len())
+            .filter(|&i| segments[i].length() >= params.min_length && observations[i].normal != [0.0; 3])
+            .collect();
+        Detector { segments, intrinsics, params, observations, voters }
+    }
+
+    fn is_consistent(&self, segment: usize, direction: &Vec3) -> bool {
+        angle_to(&self.segments[segment], &self.intrinsics.project(direction)) <= self.params.max_angle
+    }
+
+    /// Voters among `candidates` consistent with `direction`.
+    fn support(&self, candidates: &[usize], direction: &Vec3) -> Vec<usize> {
+        candidates.iter().copied().filter(|&i| self.is_consistent(i, direction)).collect()
+    }
+
+    fn weight(&self, members: &[usize]) -> f64 {
+        members.iter().map(|&i| self.observations[i].weight).sum()
+    }
+
+    /// Refines `direction` on its support until the support stops changing.
+    fn refine_on(&self, candidates: &[usize], mut direction: Vec3) -> (Vec3, Vec<usize>) {
+        let mut members = self.support(candidates, &direction);
+        for _ in 0..5 {
+            let Some(refined) = refine(&self.observations, &members) else {
+                break;
+            };
+            let refined_members = self.support(candidates, &refined);
+            if refined_members.len() < members.len() {
+                break;
+            }
+            let done = refined_members == members;
+            (direction, members) = (refined, refined_members);
+            if done {
+                break;
+            }
+        }
+        (direction, members)
+    }
+
+    /// Assigns every segment to the consistent direction closest to it.
+    fn finish(&self, directions: Vec<Vec3>) -> VanishingPoints {
+        let points: Vec<[f32; 3]> = directions.iter().map(|d| self.intrinsics.project(d)).collect();
+        let assignment = self
+            .segments
+            .iter()
+            .map(|segment| {
+                points
+                    .iter()
+                    .map(|vp| angle_to(segment, vp))
+                    .enumerate()
+                    .filter(|(_, angle)| *angle <= self.params.max_angle)
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(i, _)| i)
+            })
+            .collect();
+        VanishingPoints {
+            directions: directions.iter().map(|d| d.map(|v| v as f32)).collect(),
+            points,
+            assignment,
+        }
+    }
+}
+
+/// Vanishing points of `segments` of a `width` x `height` image, found one after
+/// the other by RANSAC: the intersection of two random segments is scored by the
+/// total length of the segments pointing at it, and the best hypothesis is
+/// refined by least squares on the Gaussian sphere before its segments are
+/// removed for the next search.
+pub fn detect_vanishing_points(
+    segments: &[LineSegment],
+    width: usize,
+    height: usize,
+    params: &VanishingPointParams,
+) -> VanishingPoints {
+    let detector = Detector::new(segments, Intrinsics::uncalibrated(width, height), params);
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let mut remaining = detector.voters.clone();
+    let mut directions = vec![];
+
+    while directions.len() < params.max_points && remaining.len() >= params.min_support.max(2) {
+        let mut best: Option<(f64, Vec3)> = None;
+        for _ in 0..params.iterations {
+            let sample = index::sample(&mut rng, remaining.len(), 2);
+            let (a, b) = (remaining[sample.index(0)], remaining[sample.index(1)]);
+            let normals = (&detector.observations[a].normal, &detector.observations[b].normal);
+            let Some(direction) = normalized(cross(normals.0, normals.1)) else {
+                continue;
+            };
+            let score = detector.weight(&detector.support(&remaining, &direction));
+            if best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, direction));
+            }
+        }
+
+        let Some((_, direction)) = best else {
+            break;
+        };
+        let (direction, members) = detector.refine_on(&remaining, direction);
+        if members.len() < params.min_support {
+            break;
+        }
+        remaining.retain(|i| !members.contains(i));
+        directions.push(direction);
+    }
+
+    detector.finish(directions)
+}
+
+/// Three orthogonal vanishing points of a Manhattan world seen by a camera with
+/// the given intrinsics. Hypotheses take the first direction from two random
+/// segments and the second one in the interpretation plane of a third segment,
+/// orthogonal to the first. The three directions of the best hypothesis are
+/// refined separately and made orthogonal again, the direction with the most
+/// support being kept as is.
+///
+/// Directions are the columns of the rotation from the Manhattan frame to the
+/// camera frame, up to sign and order, and are sorted by decreasing support.
+pub fn detect_manhattan_vanishing_points(
+    segments: &[LineSegment],
+    intrinsics: &Intrinsics,
+    params: &VanishingPointParams,
+) -> VanishingPoints {
+    let detector = Detector::new(segments, *intrinsics, params);
+    let voters = &detector.voters;
+    if voters.len() < 3 {
+        return detector.finish(vec![]);
+    }
+
+    let score = |frame: &[Vec3; 3]| -> f64 {
+        let points = frame.map(|d| intrinsics.project(&d));
+        voters
+            .iter()
+            .filter(|&&i| points.iter().any(|vp| angle_to(&segments[i], vp) <= params.max_angle))
+            .map(|&i| detector.observations[i].weight)
+            .sum()
+    };
+
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let mut best: Option<(f64, [Vec3; 3])> = None;
+    for _ in 0..params.iterations {
+        let sample = index::sample(&mut rng, voters.len(), 2);
+        let (a, b) = (voters[sample.index(0)], voters[sample.index(1)]);
+        let c = voters[rng.gen_range(0..voters.len())];
+        let observations = &detector.observations;
+        let Some(d1) = normalized(cross(&observations[a].normal, &observations[b].normal)) else {
+            continue;
+        };
+        let Some(d2) = normalized(cross(&d1, &observations[c].normal)) else {
+            continue;
+        };
+        let frame = [d1, d2, cross(&d1, &d2)];
+        let frame_score = score(&frame);
+        if best.is_none_or(|(best_score, _)| frame_score > best_score) {
+            best = Some((frame_score, frame));
+        }
+    }
+    let Some((_, frame)) = best else {
+        return detector.finish(vec![]);
+    };
+
+    // Each voter refines the direction it is closest to.
+    let mut clusters: [Vec<usize>; 3] = Default::default();
+    let points = frame.map(|d| intrinsics.project(&d));
+    for &i in voters {
+        let closest = (0..3)
+            .map(|k| (k, angle_to(&segments[i], &points[k])))
+            .filter(|(_, angle)| *angle <= params.max_angle)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((k, _)) = closest {
+            clusters[k].push(i);
+        }
+    }
+    let mut refined: Vec<(f64, Vec3)> = (0..3)
+        .map(|k| {
+            let direction = if clusters[k].len() >= params.min_support {
+                refine(&detector.observations, &clusters[k]).unwrap_or(frame[k])
+            } else {
+                frame[k]
+            };
+            (detector.weight(&clusters[k]), direction)
+        })
+        .collect();
+    refined.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let directions = orthonormalize([refined[0].1, refined[1].1, refined[2].1], &frame);
+    detector.finish(directions.to_vec())
+}
+
+/// Gram-Schmidt, strongest direction first: the first direction is kept, the
+/// second is made orthogonal to it and the third is their cross product, on the
+/// side of the third direction. When the second direction is parallel to the
+/// first, the third one or an axis of the orthonormal `frame` replaces it.
+fn orthonormalize(directions: [Vec3; 3], frame: &[Vec3; 3]) -> [Vec3; 3] {
+    let d1 = directions[0];
+    let d2 = [directions[1], directions[2]]
+        .iter()
+        .chain(frame)
+        .find_map(|d| normalized(std::array::from_fn(|k| d[k] - dot(&d1, d) * d1[k])))
+        .expect("an axis of the frame is not parallel to the first direction");
+    let mut d3 = cross(&d1, &d2);
+    if dot(&d3, &directions[2]) < 0.0 {
+        d3 = d3.map(|v| -v);
+    }
+    [d1, d2, d3]
+}
+
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{
+        detect_manhattan_vanishing_points, detect_vanishing_points, dot, orthonormalize, Intrinsics,
+        VanishingPointParams,
+    };
+    use crate::line::LineSegment;
+
+    /// Camera frame directions of the three axes of a frame rotated by `pitch`
+    /// and `yaw` degrees.
+    fn rotation(pitch: f32, yaw: f32) -> [[f32; 3]; 3] {
+        let (sp, cp) = pitch.to_radians().sin_cos();
+        let (sy, cy) = yaw.to_radians().sin_cos();
+        [[cy, 0.0, -sy], [sy * sp, cp, cy * sp], [sy * cp, -sp, cy * cp]]
+    }
+
+    fn angle_between(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+        let dot = (a[0] * b[0] + a[1] * b[1] + a[2] * b[2]).abs();
+        let norm = (a.iter().map(|v| v * v).sum::<f32>() * b.iter().map(|v| v * v).sum::<f32>()).sqrt();
+        (dot / norm).min(1.0).acos().to_degrees()
+    }
+
+    /// Segments in random directions in a 640 x 480 image.
+    fn clutter(rng: &mut StdRng, n: usize) -> Vec<LineSegment> {
+        (0..n)
+            .map(|_| {
+                let (x, y) = (rng.gen_range(50.0..590.0), rng.gen_range(50.0..430.0));
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                LineSegment::new(x, y, x + 40.0 * angle.cos(), y + 40.0 * angle.sin())
+            })
+            .collect()
+    }
+
+    /// Segments of a room seen from inside: lines along the three axes of the
+    /// rotated Manhattan frame, plus clutter, with the axis of each segment.
+    fn room(
+        intrinsics: &Intrinsics,
+        frame: &[[f32; 3]; 3],
+        rng: &mut StdRng,
+    ) -> (Vec<LineSegment>, Vec<Option<usize>>) {
+        let Intrinsics { fx, fy, cx, cy } = *intrinsics;
+        let project = |p: [f32; 3]| -> Option<(f32, f32)> {
+            let c: [f32; 3] = std::array::from_fn(|r| (0..3).map(|k| frame[k][r] * p[k]).sum());
+            (c[2] > 0.5).then(|| (fx * c[0] / c[2] + cx, fy * c[1] / c[2] + cy))
+        };
+        let (mut segments, mut axes) = (vec![], vec![]);
+        while segments.len() < 120 {
+            let axis = rng.gen_range(0..3);
+            let mut start = [rng.gen_range(-4.0..4.0), rng.gen_range(-3.0..3.0), rng.gen_range(4.0..10.0)];
+            let mut end = start;
+            start[axis] -= rng.gen_range(0.5..2.0);
+            end[axis] += rng.gen_range(0.5..2.0);
+            let (Some((x1, y1)), Some((x2, y2))) = (project(start), project(end)) else {
+                continue;
+            };
+            let noise = |rng: &mut StdRng| rng.gen_range(-0.5..0.5);
+            let segment = LineSegment::new(x1 + noise(rng), y1 + noise(rng), x2 + noise(rng), y2 + noise(rng));
+            let inside = |x: f32, y: f32| (0.0..640.0).contains(&x) && (0.0..480.0).contains(&y);
+            if segment.length() > 30.0 && inside(x1, y1) && inside(x2, y2) {
+                segments.push(segment);
+                axes.push(Some(axis));
+            }
+        }
+        segments.extend(clutter(rng, 20));
+        axes.resize(segments.len(), None);
+        (segments, axes)
+    }
+
+    #[test]
+    fn manhattan_frame_is_recovered() {
+        let intrinsics = Intrinsics { fx: 500.0, fy: 500.0, cx: 319.5, cy: 239.5 };
+        let frame = rotation(12.0, 30.0);
+        let mut rng = StdRng::seed_from_u64(5);
+        let (segments, axes) = room(&intrinsics, &frame, &mut rng);
+
+        let found = detect_manhattan_vanishing_points(&segments, &intrinsics, &VanishingPointParams::default());
+        assert_eq!(found.directions.len(), 3);
+        // Every axis is matched by one of the directions.
+        let mut axis_of = [0; 3];
+        for (axis, column) in frame.iter().enumerate() {
+            let (k, error) = (0..3)
+                .map(|k| (k, angle_between(&found.directions[k], column)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            assert!(error < 1.0, "axis {} error {} in {:?}", axis, error, found.directions);
+            axis_of[axis] = k;
+        }
+
+        let correct = (0..segments.len())
+            .filter(|&i| axes[i].is_some_and(|axis| found.assignment[i] == Some(axis_of[axis])))
+            .count();
+        let structured = axes.iter().filter(|a| a.is_some()).count();
+        assert!(correct as f32 > 0.95 * structured as f32, "{} of {}", correct, structured);
+        let first = found.assignment.iter().filter(|a| **a == Some(axis_of[0])).count();
+        assert_eq!(found.cluster(axis_of[0]).len(), first);
+    }
+
+    #[test]
+    fn converging_and_parallel_segments() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut segments = vec![];
+        // Segments pointing at (900, 200) and horizontal parallel segments.
+        for _ in 0..30 {
+            let (x, y) = (rng.gen_range(0.0..500.0), rng.gen_range(0.0..480.0));
+            let (dx, dy) = (900.0 - x, 200.0 - y);
+            let t = rng.gen_range(0.05..0.2);
+            segments.push(LineSegment::new(x, y, x + t * dx, y + t * dy));
+        }
+        for _ in 0..30 {
+            let (x, y) = (rng.gen_range(0.0..500.0), rng.gen_range(0.0..480.0));
+            segments.push(LineSegment::new(x, y, x + rng.gen_range(30.0..120.0), y));
+        }
+        segments.extend(clutter(&mut rng, 10));
+
+        let found = detect_vanishing_points(&segments, 640, 480, &VanishingPointParams::default());
+        assert!(found.points.len() >= 2);
+        let finite = (0..found.points.len()).find_map(|i| found.point(i).filter(|&(x, _)| x > 600.0 && x < 1200.0));
+        let (x, y) = finite.unwrap_or_else(|| panic!("{:?}", found.points));
+        assert!((x - 900.0).abs() < 10.0 && (y - 200.0).abs() < 10.0, "{:?}", (x, y));
+
+        let horizontal = (0..found.points.len())
+            .find(|&i| found.points[i][2].abs() < 1e-3 * found.points[i][0].abs())
+            .unwrap_or_else(|| panic!("{:?}", found.points));
+        assert_eq!(found.cluster(horizontal).iter().filter(|&&i| (30..60).contains(&i)).count(), 30);
+    }
+
+    #[test]
+    fn orthonormalize_handles_parallel_directions() {
+        let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        let diagonal = [0.6, 0.8, 0.0];
+        for directions in [
+            [diagonal, diagonal, [0.0, 0.0, 1.0]],
+            [diagonal, [-0.6, -0.8, 0.0], diagonal],
+            [[1.0, 0.0, 0.0], [1.0, 0.0, 0.0], [-1.0, 0.0, 0.0]],
+        ] {
+            let frame = orthonormalize(directions, &identity);
+            assert_eq!(frame[0], directions[0]);
+            for i in 0..3 {
+                for j in 0..3 {
+                    let expected = if i == j { 1.0 } else { 0.0 };
+                    assert!((dot(&frame[i], &frame[j]) - expected).abs() < 1e-12, "{:?}", frame);
+                }
+            }
+        }
+    }
+}
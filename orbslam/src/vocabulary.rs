@@ -0,0 +1,611 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use log::info;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::descriptors::OrbDescriptor;
+use crate::hamming::hamming_distance;
+use crate::io::{invalid_data, read_array, read_u32};
+
+/// Bag-of-words vector: word id to weight, normalised to unit L1 norm.
+pub type BowVector = BTreeMap<u32, f32>;
+
+/// Direct index: node id at the chosen level to the indices of the features
+/// that fall under it.
+pub type FeatureVector = BTreeMap<u32, Vec<usize>>;
+
+const BINARY_MAGIC: &[u8; 4] = b"BOWV";
+const MAX_KMEANS_ITERATIONS: usize = 50;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Node {
+    parent: u32,
+    children: Vec<u32>,
+    descriptor: OrbDescriptor,
+    /// IDF weight, only meaningful for words.
+    weight: f32,
+    word_id: Option<u32>,
+}
+
+impl Node {
+    fn new(parent: u32, descriptor: OrbDescriptor) -> Node {
+        Node { parent, children: vec![], descriptor, weight: 0.0, word_id: None }
+    }
+}
+
+/// Hierarchical vocabulary of binary words with TF-IDF weighting and L1 scoring,
+/// compatible with DBoW2 vocabularies such as ORB-SLAM's `ORBvoc.txt`.
+///
+/// Node 0 is the root; words are the leaves of the tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vocabulary {
+    k: usize,
+    levels: usize,
+    nodes: Vec<Node>,
+    words: Vec<u32>,
+}
+
+impl Vocabulary {
+    /// Trains a vocabulary with branching factor `k` and depth `levels` from the
+    /// descriptors of a set of training images, by hierarchical k-majority
+    /// clustering with k-means++ seeding. Word weights are the inverse document
+    /// frequencies over the training images.
+    pub fn train(images: &[Vec<OrbDescriptor>], k: usize, levels: usize, seed: u64) -> Vocabulary {
+        assert!(k >= 2 && levels >= 1, "k must be at least 2 and levels at least 1");
+        let start = Instant::now();
+
+        let mut vocabulary = Vocabulary {
+            k,
+            levels,
+            nodes: vec![Node::new(0, [0; 32])],
+            words: vec![],
+        };
+
+        let descriptors: Vec<&OrbDescriptor> = images.iter().flatten().collect();
+        let mut rng = StdRng::seed_from_u64(seed);
+        vocabulary.cluster(0, &descriptors, 1, &mut rng);
+        vocabulary.collect_words();
+        vocabulary.set_idf_weights(images);
+
+        info!(
+            "Trained a vocabulary of {} words from {} descriptors in : {:?}",
+            vocabulary.words.len(),
+            descriptors.len(),
+            start.elapsed()
+        );
+        vocabulary
+    }
+
+    fn cluster(&mut self, parent: u32, descriptors: &[&OrbDescriptor], level: usize, rng: &mut StdRng) {
+        if descriptors.is_empty() {
+            return;
+        }
+
+        let groups: Vec<(OrbDescriptor, Vec<&OrbDescriptor>)> = if descriptors.len() <= self.k {
+            descriptors.iter().map(|d| (**d, vec![*d])).collect()
+        } else {
+            k_majority(descriptors, self.k, rng)
+        };
+
+        for (center, members) in groups {
+            let id = self.nodes.len() as u32;
+            self.nodes.push(Node::new(parent, center));
+            self.nodes[parent as usize].children.push(id);
+
+            if level < self.levels {
+                self.cluster(id, &members, level + 1, rng);
+            }
+        }
+    }
+
+    fn collect_words(&mut self) {
+        self.words.clear();
+        for id in 0..self.nodes.len() {
+            if id != 0 && self.nodes[id].children.is_empty() {
+                self.nodes[id].word_id = Some(self.words.len() as u32);
+                self.words.push(id as u32);
+            }
+        }
+    }
+
+    fn set_idf_weights(&mut self, images: &[Vec<OrbDescriptor>]) {
+        let mut documents = vec![0usize; self.words.len()];
+        for image in images {
+            let mut seen = vec![false; self.words.len()];
+            for (word_id, _) in image.iter().filter_map(|descriptor| self.descend(descriptor, 0)) {
+                seen[word_id as usize] = true;
+            }
+            for (count, seen) in documents.iter_mut().zip(seen) {
+                *count += seen as usize;
+            }
+        }
+
+        let n_images = images.len() as f32;
+        for (word, count) in self.words.clone().into_iter().zip(documents) {
+            self.nodes[word as usize].weight = if count > 0 { (n_images / count as f32).ln() } else { 0.0 };
+        }
+    }
+
+    pub fn branching_factor(&self) -> usize {
+        self.k
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    pub fn size(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn word_weight(&self, word_id: u32) -> f32 {
+        self.nodes[self.words[word_id as usize] as usize].weight
+    }
+
+    /// Word of a descriptor, and the node it went through at depth
+    /// `levels - levels_up`; `None` for a vocabulary without words.
+    fn descend(&self, descriptor: &OrbDescriptor, levels_up: usize) -> Option<(u32, u32)> {
+        let target_level = self.levels.saturating_sub(levels_up);
+        let mut node = 0u32;
+        let mut level_node = 0u32;
+        let mut level = 0;
+
+        while !self.nodes[node as usize].children.is_empty() {
+            node = *self.nodes[node as usize]
+                .children
+                .iter()
+                .min_by_key(|&&child| hamming_distance(descriptor, &self.nodes[child as usize].descriptor))
+                .unwrap();
+            level += 1;
+            if level <= target_level {
+                level_node = node;
+            }
+        }
+
+        self.nodes[node as usize].word_id.map(|word_id| (word_id, level_node))
+    }
+
+    /// Word id of a single descriptor, `None` for a vocabulary without words.
+    pub fn word(&self, descriptor: &OrbDescriptor) -> Option<u32> {
+        self.descend(descriptor, 0).map(|(word_id, _)| word_id)
+    }
+
+    /// TF-IDF bag-of-words vector of an image, `None` for a vocabulary without
+    /// words.
+    pub fn transform(&self, descriptors: &[OrbDescriptor]) -> Option<BowVector> {
+        self.transform_with_features(descriptors, 0).map(|(bow, _)| bow)
+    }
+
+    /// TF-IDF bag-of-words vector of an image together with its direct index,
+    /// grouping features by their ancestor `levels_up` levels above the words;
+    /// `None` for a vocabulary without words.
+    pub fn transform_with_features(
+        &self,
+        descriptors: &[OrbDescriptor],
+        levels_up: usize,
+    ) -> Option<(BowVector, FeatureVector)> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut bow = BowVector::new();
+        let mut features = FeatureVector::new();
+
+        for (index, descriptor) in descriptors.iter().enumerate() {
+            let (word_id, node_id) = self.descend(descriptor, levels_up)?;
+            let weight = self.word_weight(word_id);
+            if weight > 0.0 {
+                *bow.entry(word_id).or_insert(0.0) += weight;
+                features.entry(node_id).or_default().push(index);
+            }
+        }
+
+        let norm: f32 = bow.values().map(|v| v.abs()).sum();
+        if norm > 0.0 {
+            for value in bow.values_mut() {
+                *value /= norm;
+            }
+        }
+
+        Some((bow, features))
+    }
+
+    /// L1 similarity of two normalised BoW vectors, in `[0, 1]`.
+    pub fn score(a: &BowVector, b: &BowVector) -> f32 {
+        let mut score = 0.0;
+        let (mut ia, mut ib) = (a.iter().peekable(), b.iter().peekable());
+        while let (Some((wa, va)), Some((wb, vb))) = (ia.peek(), ib.peek()) {
+            match wa.cmp(wb) {
+                std::cmp::Ordering::Less => {
+                    ia.next();
+                }
+                std::cmp::Ordering::Greater => {
+                    ib.next();
+                }
+                std::cmp::Ordering::Equal => {
+                    score += (*va - *vb).abs() - va.abs() - vb.abs();
+                    ia.next();
+                    ib.next();
+                }
+            }
+        }
+        -score / 2.0
+    }
+
+    /// Writes the vocabulary in a compact little-endian binary format.
+    pub fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(BINARY_MAGIC)?;
+        writer.write_all(&(self.k as u32).to_le_bytes())?;
+        writer.write_all(&(self.levels as u32).to_le_bytes())?;
+        writer.write_all(&(self.nodes.len() as u32 - 1).to_le_bytes())?;
+        for node in self.nodes.iter().skip(1) {
+            writer.write_all(&node.parent.to_le_bytes())?;
+            writer.write_all(&[node.word_id.is_some() as u8])?;
+            writer.write_all(&node.descriptor)?;
+            writer.write_all(&node.weight.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_binary<R: Read>(reader: &mut R) -> io::Result<Vocabulary> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != BINARY_MAGIC {
+            return Err(invalid_data("not a binary vocabulary"));
+        }
+
+        let k = read_u32(reader)? as usize;
+        let levels = read_u32(reader)? as usize;
+        let n_nodes = read_u32(reader)? as usize;
+
+        // The node count is not trusted for preallocation, a corrupt header would
+        // otherwise ask for gigabytes before the truncated data is noticed.
+        let mut builder = VocabularyBuilder::new(k, levels);
+        for _ in 0..n_nodes {
+            let parent = read_u32(reader)?;
+            let mut is_leaf = [0u8; 1];
+            reader.read_exact(&mut is_leaf)?;
+            let mut descriptor = [0u8; 32];
+            reader.read_exact(&mut descriptor)?;
+            let weight = f32::from_le_bytes(read_array(reader)?);
+            builder.push(parent, is_leaf[0] != 0, descriptor, weight)?;
+        }
+        builder.finish()
+    }
+
+    pub fn save_binary<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_binary(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load_binary<P: AsRef<Path>>(path: P) -> io::Result<Vocabulary> {
+        Vocabulary::read_binary(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Reads a DBoW2 text vocabulary such as ORB-SLAM's `ORBvoc.txt`: a header
+    /// line `k L scoring weighting`, then one line per node with the parent id,
+    /// a leaf flag, the 32 descriptor bytes and the weight. Only TF-IDF weighting
+    /// with L1 scoring is supported.
+    pub fn read_text<R: BufRead>(reader: R) -> io::Result<Vocabulary> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or_else(|| invalid_data("empty vocabulary"))??;
+        let header: Vec<usize> = header
+            .split_whitespace()
+            .map(|v| v.parse().map_err(|_| invalid_data("bad vocabulary header")))
+            .collect::<io::Result<_>>()?;
+        let [k, levels, scoring, weighting] = header[..] else {
+            return Err(invalid_data("bad vocabulary header"));
+        };
+        if scoring != 0 || weighting != 0 {
+            return Err(invalid_data("only L1 scoring with TF-IDF weighting is supported"));
+        }
+
+        let mut builder = VocabularyBuilder::new(k, levels);
+        for line in lines {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() != 35 {
+                return Err(invalid_data("bad vocabulary node"));
+            }
+
+            let parent: u32 = fields[0].parse().map_err(bad_node)?;
+            let is_leaf = fields[1].parse::<u8>().map_err(bad_node)? != 0;
+            let mut descriptor = [0u8; 32];
+            for (byte, field) in descriptor.iter_mut().zip(&fields[2..34]) {
+                *byte = field.parse().map_err(bad_node)?;
+            }
+            let weight: f32 = fields[34].parse().map_err(bad_node)?;
+            builder.push(parent, is_leaf, descriptor, weight)?;
+        }
+        builder.finish()
+    }
+
+    pub fn load_text<P: AsRef<Path>>(path: P) -> io::Result<Vocabulary> {
+        let start = Instant::now();
+        let vocabulary = Vocabulary::read_text(BufReader::new(File::open(path)?))?;
+        info!("Loaded a vocabulary of {} words in : {:?}", vocabulary.size(), start.elapsed());
+        Ok(vocabulary)
+    }
+}
+
+/// Rebuilds the tree from nodes listed parent first, as both file formats store them.
+struct VocabularyBuilder {
+    vocabulary: Vocabulary,
+}
+
+impl VocabularyBuilder {
+    fn new(k: usize, levels: usize) -> Self {
+        let nodes = vec![Node::new(0, [0; 32])];
+        VocabularyBuilder { vocabulary: Vocabulary { k, levels, nodes, words: vec![] } }
+    }
+
+    fn push(&mut self, parent: u32, is_leaf: bool, descriptor: OrbDescriptor, weight: f32) -> io::Result<()> {
+        let nodes = &mut self.vocabulary.nodes;
+        let id = nodes.len() as u32;
+        if parent >= id {
+            return Err(invalid_data("vocabulary node listed before its parent"));
+        }
+
+        let mut node = Node::new(parent, descriptor);
+        node.weight = weight;
+        if is_leaf {
+            node.word_id = Some(self.vocabulary.words.len() as u32);
+            self.vocabulary.words.push(id);
+        }
+        nodes.push(node);
+        nodes[parent as usize].children.push(id);
+        Ok(())
+    }
+
+    /// The vocabulary, once checked that descriptors always end on a word: it
+    /// has words and the leaves of the tree are exactly its words.
+    fn finish(self) -> io::Result<Vocabulary> {
+        let vocabulary = self.vocabulary;
+        if vocabulary.is_empty() {
+            return Err(invalid_data("vocabulary without words"));
+        }
+        let consistent = vocabulary
+            .nodes
+            .iter()
+            .skip(1)
+            .all(|node| node.children.is_empty() == node.word_id.is_some());
+        if !consistent {
+            return Err(invalid_data("vocabulary leaves and words differ"));
+        }
+        Ok(vocabulary)
+    }
+}
+
+/// k-majority clustering: k-medians for binary descriptors where centers are the
+/// bitwise majority of their members. Returns the centers with their members.
+fn k_majority<'a>(
+    descriptors: &[&'a OrbDescriptor],
+    k: usize,
+    rng: &mut StdRng,
+) -> Vec<(OrbDescriptor, Vec<&'a OrbDescriptor>)> {
+    let mut centers = kmeans_pp_seeds(descriptors, k, rng);
+    let mut assignment = vec![usize::MAX; descriptors.len()];
+
+    for _ in 0..MAX_KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (descriptor, cluster) in descriptors.iter().zip(assignment.iter_mut()) {
+            let nearest = nearest_center(descriptor, &centers);
+            if nearest != *cluster {
+                *cluster = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        for (c, center) in centers.iter_mut().enumerate() {
+            let members: Vec<&OrbDescriptor> = descriptors
+                .iter()
+                .zip(assignment.iter())
+                .filter(|(_, cluster)| **cluster == c)
+                .map(|(d, _)| *d)
+                .collect();
+            if !members.is_empty() {
+                *center = majority(&members);
+            }
+        }
+    }
+
+    let mut groups: Vec<(OrbDescriptor, Vec<&OrbDescriptor>)> = centers.into_iter().map(|c| (c, vec![])).collect();
+    for (descriptor, cluster) in descriptors.iter().zip(assignment) {
+        groups[cluster].1.push(*descriptor);
+    }
+    groups.retain(|(_, members)| !members.is_empty());
+    groups
+}
+
+fn nearest_center(descriptor: &OrbDescriptor, centers: &[OrbDescriptor]) -> usize {
+    centers
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, center)| hamming_distance(descriptor, *center))
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+/// Bit `i` of the result is set when at least half of the descriptors have it set.
+fn majority(descriptors: &[&OrbDescriptor]) -> OrbDescriptor {
+    let mut counts = [0usize; 256];
+    for descriptor in descriptors {
+        for (bit, count) in counts.iter_mut().enumerate() {
+            *count += ((descriptor[bit / 8] >> (bit % 8)) & 1) as usize;
+        }
+    }
+
+    let half = descriptors.len().div_ceil(2);
+    let mut center = [0u8; 32];
+    for (bit, count) in counts.iter().enumerate() {
+        if *count >= half {
+            center[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    center
+}
+
+/// k-means++ seeding: every new seed is drawn with probability proportional to
+/// the squared distance to the closest seed so far.
+fn kmeans_pp_seeds(descriptors: &[&OrbDescriptor], k: usize, rng: &mut StdRng) -> Vec<OrbDescriptor> {
+    let mut centers = vec![*descriptors[rng.gen_range(0..descriptors.len())]];
+    let mut min_dist: Vec<f64> = descriptors
+        .iter()
+        .map(|d| (hamming_distance(*d, &centers[0]) as f64).powi(2))
+        .collect();
+
+    while centers.len() < k {
+        let total: f64 = min_dist.iter().sum();
+        if total == 0.0 {
+            break;
+        }
+
+        let mut target = rng.gen_range(0.0..total);
+        let mut chosen = descriptors.len() - 1;
+        for (i, dist) in min_dist.iter().enumerate() {
+            if target < *dist {
+                chosen = i;
+                break;
+            }
+            target -= dist;
+        }
+
+        let center = *descriptors[chosen];
+        for (dist, descriptor) in min_dist.iter_mut().zip(descriptors) {
+            *dist = dist.min((hamming_distance(*descriptor, &center) as f64).powi(2));
+        }
+        centers.push(center);
+    }
+    centers
+}
+
+fn bad_node<E>(_: E) -> io::Error {
+    invalid_data("bad vocabulary node")
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, ErrorKind};
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::Vocabulary;
+    use crate::descriptors::OrbDescriptor;
+
+    fn noisy(rng: &mut StdRng, descriptor: &OrbDescriptor, bits: usize) -> OrbDescriptor {
+        let mut noisy = *descriptor;
+        for _ in 0..bits {
+            let bit = rng.gen_range(0..256);
+            noisy[bit / 8] ^= 1 << (bit % 8);
+        }
+        noisy
+    }
+
+    /// Images of four "places", each place seeing its own subset of 200 patterns.
+    fn training_images(rng: &mut StdRng) -> (Vec<OrbDescriptor>, Vec<Vec<OrbDescriptor>>) {
+        let patterns: Vec<OrbDescriptor> = (0..200).map(|_| rng.gen()).collect();
+        let images = (0..12)
+            .map(|i| {
+                let place = i % 4;
+                (0..150)
+                    .map(|_| {
+                        let pattern = place * 50 + rng.gen_range(0..50);
+                        noisy(rng, &patterns[pattern], 8)
+                    })
+                    .collect()
+            })
+            .collect();
+        (patterns, images)
+    }
+
+    #[test]
+    fn bow_scores_separate_places() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let (patterns, images) = training_images(&mut rng);
+        let vocabulary = Vocabulary::train(&images, 6, 3, 0);
+        assert!(vocabulary.size() > 50);
+
+        let query: Vec<OrbDescriptor> = (0..100).map(|i| noisy(&mut rng, &patterns[50 + i % 50], 8)).collect();
+        let (query_bow, features) = vocabulary.transform_with_features(&query, 1).unwrap();
+        assert!((query_bow.values().sum::<f32>() - 1.0).abs() < 1e-4);
+        assert_eq!(features.values().map(|f| f.len()).sum::<usize>(), 100);
+
+        let scores: Vec<f32> = images
+            .iter()
+            .map(|image| Vocabulary::score(&query_bow, &vocabulary.transform(image).unwrap()))
+            .collect();
+        for (i, score) in scores.iter().enumerate() {
+            if i % 4 == 1 {
+                assert!(*score > 0.3, "same place scored {}", score);
+            } else {
+                assert!(*score < 0.1, "other place scored {}", score);
+            }
+        }
+        assert!((Vocabulary::score(&query_bow, &query_bow) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn binary_and_text_round_trip() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let (_, images) = training_images(&mut rng);
+        let vocabulary = Vocabulary::train(&images, 4, 2, 0);
+
+        let mut binary = vec![];
+        vocabulary.write_binary(&mut binary).unwrap();
+        assert_eq!(Vocabulary::read_binary(&mut Cursor::new(&binary)).unwrap(), vocabulary);
+
+        let mut text = format!("{} {} 0 0\n", vocabulary.k, vocabulary.levels);
+        for node in vocabulary.nodes.iter().skip(1) {
+            text += &format!("{} {} ", node.parent, node.word_id.is_some() as u8);
+            for byte in node.descriptor {
+                text += &format!("{} ", byte);
+            }
+            text += &format!("{}\n", node.weight);
+        }
+        assert_eq!(Vocabulary::read_text(Cursor::new(text)).unwrap(), vocabulary);
+
+        assert!(Vocabulary::read_text(Cursor::new("10 6 1 0\n")).is_err());
+        assert!(Vocabulary::read_binary(&mut Cursor::new(b"nope")).is_err());
+    }
+
+    #[test]
+    fn vocabularies_without_words() {
+        let vocabulary = Vocabulary::train(&[vec![]], 4, 2, 0);
+        assert!(vocabulary.is_empty());
+        assert_eq!(vocabulary.word(&[0; 32]), None);
+        assert_eq!(vocabulary.transform(&[[0; 32]]), None);
+
+        // An inner node without children, and a huge node count with no nodes.
+        let node = |parent: u32, is_leaf: u8| {
+            let mut bytes = parent.to_le_bytes().to_vec();
+            bytes.push(is_leaf);
+            bytes.extend([0u8; 36]);
+            bytes
+        };
+        let header = |n_nodes: u32| {
+            [&b"BOWV"[..], &4u32.to_le_bytes(), &2u32.to_le_bytes(), &n_nodes.to_le_bytes()].concat()
+        };
+        let dangling = [header(2), node(0, 1), node(0, 0)].concat();
+        assert_eq!(
+            Vocabulary::read_binary(&mut Cursor::new(dangling)).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(Vocabulary::read_binary(&mut Cursor::new(header(0))).is_err());
+        assert!(Vocabulary::read_binary(&mut Cursor::new(header(u32::MAX))).is_err());
+    }
+}